@@ -5,27 +5,31 @@ use std::fs::{File, write};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
+use std::time::Duration;
 use std::{env, fmt, fs};
 
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
 use color_eyre::eyre::Result;
-use color_eyre::eyre::{Context, OptionExt};
+use color_eyre::eyre::{Context, OptionExt, eyre};
 use etcetera::base_strategy::BaseStrategy;
 use indexmap::IndexMap;
+use itertools::Itertools;
 use merge2::Merge;
 use regex::Regex;
 use regex_split::RegexSplit;
 use rust_i18n::t;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
 use tracing::{debug, error};
+use wildmatch::WildMatch;
 
 use crate::execution_context::RunType;
 use crate::step::{DEPRECATED_STEPS, Step};
 use crate::sudo::SudoKind;
 use crate::terminal::print_warning;
-use crate::utils::string_prepend_str;
+use crate::utils::{hostname, string_prepend_str};
 
 // TODO: Add i18n to this. Tracking issue: https://github.com/topgrade-rs/topgrade/issues/859
 pub static EXAMPLE_CONFIG: &str = include_str!("../config.example.toml");
@@ -47,14 +51,14 @@ macro_rules! str_value {
 
 pub type Commands = IndexMap<String, String>;
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Include {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     paths: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Containers {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
@@ -62,15 +66,47 @@ pub struct Containers {
     runtime: Option<ContainerRuntime>,
     system_prune: Option<bool>,
     use_sudo: Option<bool>,
+    mode: Option<ContainersMode>,
+
+    /// Only update the images with one of these labels (`key` or `key=value`), or used by a
+    /// container with one of them
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    include_labels: Option<Vec<String>>,
+
+    /// Don't update the images with one of these labels (`key` or `key=value`), or used by a
+    /// container with one of them
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    exclude_labels: Option<Vec<String>>,
+
+    /// Runtimes to update, each with its own settings (default: `runtime` with the settings above)
+    runtimes: Option<Vec<RuntimeEntry>>,
+
+    /// Compose files to pull and bring up (glob supported)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    compose_projects: Option<Vec<String>>,
+
+    compose_remove_orphans: Option<bool>,
+
+    /// Running containers to run Topgrade in (glob supported)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    exec_targets: Option<Vec<String>>,
+
+    /// Run Topgrade in the running containers with one of these labels (`key` or `key=value`)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    exec_labels: Option<Vec<String>>,
+
+    /// Steps to run in the containers (default: `["system"]`)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    exec_only: Option<Vec<Step>>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Mandb {
     enable: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Git {
     max_concurrency: Option<usize>,
@@ -78,17 +114,115 @@ pub struct Git {
     #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
     arguments: Option<String>,
 
-    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
-    repos: Option<Vec<String>>,
+    #[merge(strategy = merge_git_repos)]
+    repos: Option<GitRepos>,
 
     pull_predefined: Option<bool>,
 
+    strategy: Option<GitStrategy>,
+
+    /// Directories to search for repositories to pull
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    roots: Option<Vec<String>>,
+
+    /// Default of the `verify_signatures` of the repositories
+    verify_signatures: Option<bool>,
+
+    /// Default of the `trusted_keys` of the repositories
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    trusted_keys: Option<Vec<String>>,
+
+    /// Default of the `allowed_signers` of the repositories
+    allowed_signers: Option<String>,
+
+    /// How deep to search the `roots` for repositories (default: 3)
+    max_depth: Option<usize>,
+
+    /// How many hours to reuse the repositories found in the `roots` (default: 24, 0 to search every run)
+    discovery_cache_hours: Option<u64>,
+
     fetch_only: Option<bool>,
 
     fallback_to_fetch_default: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+/// Additional Git repositories to pull, as a list of patterns or a table of patterns to settings.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(untagged)]
+pub enum GitRepos {
+    /// Repositories to pull (glob supported)
+    Patterns(Vec<String>),
+    /// Repositories to pull (glob supported), with their settings
+    Settings(IndexMap<String, GitRepoSettings>),
+}
+
+impl GitRepos {
+    fn into_settings(self) -> IndexMap<String, GitRepoSettings> {
+        match self {
+            GitRepos::Patterns(patterns) => patterns
+                .into_iter()
+                .map(|pattern| (pattern, GitRepoSettings::default()))
+                .collect(),
+            GitRepos::Settings(settings) => settings,
+        }
+    }
+}
+
+/// Merge the repositories of `right` into `left`: patterns are prepended like other lists,
+/// and settings are merged by pattern.
+fn merge_git_repos(left: &mut Option<GitRepos>, right: &mut Option<GitRepos>) {
+    match (left.take(), right.take()) {
+        (Some(GitRepos::Patterns(mut left_patterns)), Some(GitRepos::Patterns(mut right_patterns))) => {
+            merge2::vec::prepend(&mut left_patterns, &mut right_patterns);
+            *left = Some(GitRepos::Patterns(left_patterns));
+        }
+        (Some(left_repos), Some(right_repos)) => {
+            let mut left_settings = Some(left_repos.into_settings());
+            crate::utils::merge_strategies::indexmap_recursive_merge_opt(
+                &mut left_settings,
+                &mut Some(right_repos.into_settings()),
+            );
+            *left = left_settings.map(GitRepos::Settings);
+        }
+        (left_repos, right_repos) => *left = left_repos.or(right_repos),
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GitRepoSettings {
+    strategy: Option<GitStrategy>,
+
+    /// Only update the repository when all the incoming commits are signed by a trusted key
+    verify_signatures: Option<bool>,
+
+    /// Fingerprints of the GPG or SSH keys trusted to sign the commits (default: the keys trusted by git)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    trusted_keys: Option<Vec<String>>,
+
+    /// The `gpg.ssh.allowedSignersFile` checking the SSH signatures
+    allowed_signers: Option<String>,
+
+    /// A shell command to run in the repository after its revision changed, once all the repositories are pulled
+    on_change: Option<String>,
+}
+
+/// How to update a Git repository.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GitStrategy {
+    /// `git pull --ff-only`, failing when the repository has local commits
+    #[default]
+    FfOnly,
+    /// `git pull --rebase`, rebasing the local commits that were not pushed anywhere
+    Rebase,
+    /// Like `rebase`, stashing the uncommitted changes first and restoring them afterwards
+    AutostashRebase,
+    /// `git fetch`
+    Fetch,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Vagrant {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
@@ -98,7 +232,109 @@ pub struct Vagrant {
     always_suspend: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Copy, Clone)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Chroot {
+    /// Root directories of the chroots or the systemd-nspawn machines to update (glob supported)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    directories: Option<Vec<String>>,
+
+    method: Option<ChrootMethod>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChrootMethod {
+    /// `systemd-nspawn` when it is installed, `chroot` otherwise
+    #[default]
+    Auto,
+    /// `systemd-nspawn --directory`
+    Nspawn,
+    /// `chroot`, with /proc, /sys, /dev and /etc/resolv.conf of the host mounted in the directory
+    Chroot,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Incus {
+    /// Instances to update (glob supported; default: all of them)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    instances: Option<Vec<String>>,
+
+    /// Instances not to update (glob supported)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    exclude: Option<Vec<String>>,
+
+    /// Start the stopped instances for the update, and stop them afterwards
+    power_on: Option<bool>,
+
+    mode: Option<IncusMode>,
+
+    /// Steps to run in the instances in the `topgrade` mode (default: `["system"]`)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    only: Option<Vec<Step>>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IncusMode {
+    /// Run the package manager of the instance
+    #[default]
+    PackageManager,
+    /// Push the running Topgrade executable to the instance and run it there
+    Topgrade,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteHost {
+    /// Host to connect to (default: the name of the table)
+    hostname: Option<String>,
+    user: Option<String>,
+    port: Option<u16>,
+    jump_host: Option<String>,
+
+    #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
+    ssh_arguments: Option<String>,
+
+    topgrade_path: Option<String>,
+
+    #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
+    topgrade_arguments: Option<String>,
+
+    assume_yes: Option<bool>,
+
+    /// Install this Topgrade on the host if it is missing or older
+    bootstrap: Option<bool>,
+
+    /// Configuration to send to the host: `local` or the path to a file
+    config: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Inventory {
+    #[merge(strategy = crate::utils::merge_strategies::indexmap_recursive_merge_opt)]
+    hosts: Option<IndexMap<String, RemoteHost>>,
+
+    #[merge(strategy = crate::utils::merge_strategies::indexmap_merge_opt)]
+    groups: Option<IndexMap<String, Vec<String>>>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Remotes {
+    /// Path to a TOML file with more `hosts` and `groups`
+    inventory: Option<String>,
+
+    #[merge(strategy = crate::utils::merge_strategies::indexmap_recursive_merge_opt)]
+    hosts: Option<IndexMap<String, RemoteHost>>,
+
+    #[merge(strategy = crate::utils::merge_strategies::indexmap_merge_opt)]
+    groups: Option<IndexMap<String, Vec<String>>>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Copy, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum UpdatesAutoReboot {
     Yes,
@@ -107,7 +343,7 @@ pub enum UpdatesAutoReboot {
     Ask,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Windows {
     accept_all_updates: Option<bool>,
@@ -120,7 +356,7 @@ pub struct Windows {
     winget_use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Python {
     enable_pip_review: Option<bool>,
@@ -130,7 +366,7 @@ pub struct Python {
     poetry_force_self_update: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Conda {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
@@ -140,52 +376,108 @@ pub struct Conda {
     env_paths: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Distrobox {
     use_root: Option<bool>,
 
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     containers: Option<Vec<String>>,
+
+    mode: Option<DistroboxMode>,
+
+    /// Steps to run in the boxes in the `topgrade` mode (default: all of them)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    only: Option<Vec<Step>>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Toolbx {
+    /// Steps to run in the toolboxes (default: `["system"]`, unless a toolbox has its own configuration)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    only: Option<Vec<Step>>,
+
+    /// Toolboxes not to update (wildcard supported)
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    exclude: Option<Vec<String>>,
+
+    /// Extra arguments of Topgrade in the toolboxes
+    #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
+    arguments: Option<String>,
+
+    /// Settings of the toolboxes, by name
+    #[merge(strategy = crate::utils::merge_strategies::indexmap_recursive_merge_opt)]
+    containers: Option<IndexMap<String, ToolboxSettings>>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ToolboxSettings {
+    /// Steps to run in the toolbox, instead of the `only` of `[toolbx]`
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    only: Option<Vec<Step>>,
+
+    /// Extra arguments of Topgrade in the toolbox, after the `arguments` of `[toolbx]`
+    #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
+    arguments: Option<String>,
+}
+
+/// How to run Topgrade in a toolbox, with its settings resolved.
+#[derive(Debug, PartialEq)]
+pub struct Toolbox {
+    /// Steps to run, `None` to use the default ones
+    pub only: Option<Vec<Step>>,
+    pub arguments: Vec<String>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DistroboxMode {
+    /// Run `distrobox upgrade`, updating the packages of the boxes
+    #[default]
+    Upgrade,
+    /// Run Topgrade in each box
+    Topgrade,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Yarn {
     use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct VitePlus {
     use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Npm {
     use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Skills {
     package_manager: Option<SkillsPackageManager>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Deno {
     version: Option<String>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Chezmoi {
     exclude_encrypted: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Mise {
     bump: Option<bool>,
@@ -196,25 +488,25 @@ pub struct Mise {
     silent: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Firmware {
     upgrade: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Flatpak {
     use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Pixi {
     include_release_notes: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Brew {
     greedy_cask: Option<bool>,
@@ -224,14 +516,14 @@ pub struct Brew {
     fetch_head: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Go {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     gup_exclude: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, Copy, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum ArchPackageManager {
     #[default]
@@ -247,7 +539,7 @@ pub enum ArchPackageManager {
     Yay,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, Copy, Default, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum SkillsPackageManager {
     #[default]
@@ -256,7 +548,58 @@ pub enum SkillsPackageManager {
     Bun,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Default)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeEntry {
+    /// Name of the entry in the summary (default: the runtime, and whether it uses sudo)
+    name: Option<String>,
+    runtime: Option<ContainerRuntime>,
+    use_sudo: Option<bool>,
+    ignored_containers: Option<Vec<String>>,
+    system_prune: Option<bool>,
+    mode: Option<ContainersMode>,
+    include_labels: Option<Vec<String>>,
+    exclude_labels: Option<Vec<String>>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainersMode {
+    /// Pull the images of the containers
+    #[default]
+    Pull,
+    /// Run `podman auto-update`, updating the containers with an `io.containers.autoupdate` label
+    AutoUpdate,
+}
+
+/// A container runtime to update, with its settings resolved.
+#[derive(Debug)]
+pub struct ContainerRuntimeConfig {
+    pub name: Option<String>,
+    pub runtime: ContainerRuntime,
+    pub use_sudo: bool,
+    /// Containers to ignore (wildcard supported)
+    pub ignored_containers: Vec<String>,
+    pub system_prune: bool,
+    pub mode: ContainersMode,
+    /// Labels selecting the images to update (`key` or `key=value`), all of them if empty
+    pub include_labels: Vec<String>,
+    /// Labels selecting the images not to update (`key` or `key=value`)
+    pub exclude_labels: Vec<String>,
+}
+
+impl ContainerRuntimeConfig {
+    /// Name of the runtime in the summary, e.g. `docker (sudo)`.
+    pub fn name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None if self.use_sudo => format!("{} (sudo)", self.runtime),
+            None => self.runtime.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum ContainerRuntime {
     #[default] // defaults to a popular choice
@@ -273,7 +616,7 @@ impl fmt::Display for ContainerRuntime {
     }
 }
 
-#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, Copy, Default, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 pub enum NixHandler {
     #[default]
@@ -282,7 +625,7 @@ pub enum NixHandler {
     Vanilla,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Linux {
     #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
@@ -341,13 +684,13 @@ pub struct Linux {
     wsl_use_windows_path: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Composer {
     self_update: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Vim {
     force_plug_update: Option<bool>,
@@ -355,7 +698,7 @@ pub struct Vim {
     vim_pack_prune: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Misc {
     allow_root: Option<bool>,
@@ -385,6 +728,12 @@ pub struct Misc {
 
     remote_topgrade_path: Option<String>,
 
+    remote_parallel: Option<usize>,
+
+    remote_bootstrap: Option<bool>,
+
+    remote_config: Option<String>,
+
     #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
     ssh_arguments: Option<String>,
 
@@ -433,7 +782,7 @@ pub struct Misc {
     nix_handler: Option<NixHandler>,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, ValueEnum, Default)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, ValueEnum, Default)]
 #[clap(rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 pub enum TmuxSessionMode {
@@ -443,7 +792,7 @@ pub enum TmuxSessionMode {
 }
 
 /// Controls when the end-of-run desktop notification is sent.
-#[derive(Clone, Copy, Debug, Deserialize, ValueEnum, Default)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, ValueEnum, Default)]
 #[clap(rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 pub enum NotifyEnd {
@@ -461,19 +810,36 @@ pub struct TmuxConfig {
     pub session_mode: TmuxSessionMode,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+/// A remote host to run Topgrade on, with its settings resolved.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Remote {
+    /// Name of the host in the configuration
+    pub name: String,
+    /// `[user@]hostname` to connect to
+    pub destination: String,
+    pub ssh_arguments: Vec<String>,
+    /// Path to Topgrade on the host
+    pub topgrade_path: String,
+    pub topgrade_arguments: Vec<String>,
+    /// Install this Topgrade on the host if it is missing or older
+    pub bootstrap: bool,
+    /// Configuration to send to the host for the run: `local` or the path to a file
+    pub config: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Lensfun {
     use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct JuliaConfig {
     startup_file: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Zigup {
     target_versions: Option<Vec<String>>,
@@ -482,26 +848,26 @@ pub struct Zigup {
     cleanup: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct VscodeConfig {
     profile: Option<String>,
     cleanup_obsolete_extensions: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct DoomConfig {
     aot: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Flutter {
     force: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Cargo {
     git: Option<bool>,
@@ -509,19 +875,19 @@ pub struct Cargo {
     locked: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Rustup {
     channels: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Pkgfile {
     enable: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, JsonSchema, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 /// Configuration file
 pub struct ConfigFile {
@@ -597,6 +963,15 @@ pub struct ConfigFile {
     #[merge(strategy = merge2::option::recursive)]
     vagrant: Option<Vagrant>,
 
+    #[merge(strategy = merge2::option::recursive)]
+    incus: Option<Incus>,
+
+    #[merge(strategy = merge2::option::recursive)]
+    chroot: Option<Chroot>,
+
+    #[merge(strategy = merge2::option::recursive)]
+    remotes: Option<Remotes>,
+
     #[merge(strategy = merge2::option::recursive)]
     flatpak: Option<Flatpak>,
 
@@ -606,6 +981,9 @@ pub struct ConfigFile {
     #[merge(strategy = merge2::option::recursive)]
     distrobox: Option<Distrobox>,
 
+    #[merge(strategy = merge2::option::recursive)]
+    toolbx: Option<Toolbx>,
+
     #[merge(strategy = merge2::option::recursive)]
     lensfun: Option<Lensfun>,
 
@@ -635,6 +1013,22 @@ pub struct ConfigFile {
 
     #[merge(strategy = merge2::option::recursive)]
     viteplus: Option<VitePlus>,
+
+    /// Keys users cannot override, e.g. `misc.sudo_command`. Only supported in the system-wide configuration.
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    locked: Option<Vec<String>>,
+
+    /// Overrides applied when the hostname matches the glob
+    #[merge(strategy = crate::utils::merge_strategies::indexmap_recursive_merge_opt)]
+    hosts: Option<IndexMap<String, ConfigFile>>,
+
+    /// Overrides applied on the given operating system (`linux`, `macos`, `windows`, ...)
+    #[merge(strategy = crate::utils::merge_strategies::indexmap_recursive_merge_opt)]
+    os: Option<IndexMap<String, ConfigFile>>,
+
+    /// Overrides applied on the given Linux distribution (`arch`, `debian`, `fedora`, `nixos`, ...)
+    #[merge(strategy = crate::utils::merge_strategies::indexmap_recursive_merge_opt)]
+    distro: Option<IndexMap<String, ConfigFile>>,
 }
 
 fn config_directory() -> PathBuf {
@@ -651,6 +1045,77 @@ struct ConfigFileIncludeOnly {
     include: Option<Include>,
 }
 
+/// Directory of the system-wide configuration: `topgrade/topgrade.toml` and `topgrade.d/` in it.
+#[cfg(unix)]
+const SYSTEM_CONFIG_DIRECTORY: &str = "/etc";
+
+/// Prefix of the environment variables overriding configuration keys.
+///
+/// The rest of the name is the path of the key, with sections separated by `__`,
+/// e.g. `TOPGRADE__MISC__DISABLE=containers,flatpak` sets `disable` in `[misc]`.
+const ENV_PREFIX: &str = "TOPGRADE__";
+
+/// Where a configuration value was set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigSource {
+    File(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::File(path) => write!(f, "file:{}", path.display()),
+            ConfigSource::Env(name) => write!(f, "env:{name}"),
+        }
+    }
+}
+
+/// The configuration values set by each source, highest precedence first.
+///
+/// For scalar values the first source wins, lists and argument strings are
+/// combined from all sources.
+#[derive(Debug, Default)]
+pub struct ConfigSources(Vec<(ConfigSource, String, toml::Value)>);
+
+impl ConfigSources {
+    /// Record every value of `table` as set by `source`.
+    fn record(&mut self, table: &toml::Table, source: &ConfigSource) {
+        fn flatten(table: &toml::Table, prefix: &str, out: &mut Vec<(String, toml::Value)>) {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                match value {
+                    toml::Value::Table(table) => flatten(table, &key, out),
+                    value => out.push((key, value.clone())),
+                }
+            }
+        }
+
+        let mut values = Vec::new();
+        flatten(table, "", &mut values);
+        self.0
+            .extend(values.into_iter().map(|(key, value)| (source.clone(), key, value)));
+    }
+
+    /// Append the sources of a lower precedence layer.
+    fn extend(&mut self, other: ConfigSources) {
+        self.0.extend(other.0);
+    }
+}
+
+impl fmt::Display for ConfigSources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (source, key, value) in &self.0 {
+            writeln!(f, "{source}\t{key} = {value}")?;
+        }
+        Ok(())
+    }
+}
+
 impl ConfigFile {
     /// Returns the main config file and any additional config files
     /// 0 = main config file
@@ -723,10 +1188,73 @@ impl ConfigFile {
         Ok(res)
     }
 
-    /// Read the configuration file.
+    /// Read the system-wide and the user configuration.
+    ///
+    /// The user configuration takes precedence over the system-wide one, except for the keys
+    /// listed in the system-wide `locked`. If no configuration file exists, the function returns
+    /// the default ConfigFile.
+    fn read(config_path: Option<PathBuf>, sources: &mut ConfigSources) -> Result<ConfigFile> {
+        let mut system_sources = ConfigSources::default();
+        #[cfg(unix)]
+        let mut system = Self::read_system(Path::new(SYSTEM_CONFIG_DIRECTORY), &mut system_sources);
+        #[cfg(windows)]
+        let mut system = Self::default();
+        let locked = system.locked.clone().unwrap_or_default();
+
+        let config_directory = config_directory();
+        let mut result = if config_path.is_some() || config_directory.is_dir() {
+            // Keep the system-wide configuration, and its locked keys, if the user configuration is broken
+            Self::read_user(config_path, &locked, sources).unwrap_or_else(|e| {
+                error!("failed to load configuration: {e}");
+                Self::default()
+            })
+        } else {
+            debug!("Configuration directory {} does not exist", config_directory.display());
+            Self::default()
+        };
+
+        if result.locked.take().is_some() {
+            error!("`locked` is only supported in the system-wide configuration, ignoring it");
+        }
+        result.merge(&mut system);
+        sources.extend(system_sources);
+
+        debug!("Loaded configuration: {:?}", result);
+        Ok(result)
+    }
+
+    /// Read the system-wide configuration in `directory`: `topgrade.d/*`, then `topgrade/topgrade.toml`.
     ///
-    /// If the configuration file does not exist, the function returns the default ConfigFile.
-    fn read(config_path: Option<PathBuf>) -> Result<ConfigFile> {
+    /// Files that cannot be read or deserialized are reported and skipped.
+    #[cfg(unix)]
+    fn read_system(directory: &Path, sources: &mut ConfigSources) -> ConfigFile {
+        let mut result = Self::default();
+
+        let mut paths = Self::find_topgrade_d_configs(directory).unwrap_or_else(|e| {
+            error!("{e:?}");
+            Vec::new()
+        });
+        paths.push(directory.join("topgrade/topgrade.toml"));
+
+        for path in paths.iter().filter(|path| path.exists()) {
+            debug!("System-wide configuration at {}", path.display());
+            let parsed = fs::read_to_string(path)
+                .wrap_err_with(|| format!("Unable to read {}", path.display()))
+                .and_then(|contents| {
+                    Self::parse(&contents, path, &[], sources)
+                        .wrap_err_with(|| format!("Failed to deserialize {}", path.display()))
+                });
+            match parsed {
+                Ok(mut parsed) => result.merge(&mut parsed),
+                Err(e) => error!("{e:?}"),
+            }
+        }
+
+        result
+    }
+
+    /// Read the user configuration file, ignoring the values of the `locked` keys.
+    fn read_user(config_path: Option<PathBuf>, locked: &[String], sources: &mut ConfigSources) -> Result<ConfigFile> {
         let mut result = Self::default();
 
         let config_path = if let Some(path) = config_path {
@@ -742,9 +1270,10 @@ impl ConfigFile {
                 let include_contents = fs::read_to_string(&include).inspect_err(|_| {
                     error!("Unable to read {}", include.display());
                 })?;
-                let mut include_contents_parsed = toml::from_str(include_contents.as_str()).inspect_err(|_| {
-                    error!("Failed to deserialize {}", include.display());
-                })?;
+                let mut include_contents_parsed = Self::parse(&include_contents, &include, locked, sources)
+                    .inspect_err(|_| {
+                        error!("Failed to deserialize {}", include.display());
+                    })?;
 
                 result.merge(&mut include_contents_parsed);
             }
@@ -787,7 +1316,7 @@ impl ConfigFile {
                                 continue;
                             }
                         };
-                        match toml::from_str::<Self>(&include_contents) {
+                        match Self::parse(&include_contents, &include_path, locked, sources) {
                             Ok(mut include_parsed) => result.merge(&mut include_parsed),
                             Err(e) => {
                                 error!("Failed to deserialize {}: {e}", include_path.display(),);
@@ -798,16 +1327,234 @@ impl ConfigFile {
                 }
             }
 
-            match toml::from_str::<Self>(contents) {
-                Ok(mut contents) => result.merge(&mut contents),
+            match Self::parse(contents, &config_path, locked, sources) {
+                Ok(mut parsed) => result.merge(&mut parsed),
                 Err(e) => error!("Failed to deserialize {}: {e}", config_path.display(),),
             }
         }
 
-        debug!("Loaded configuration: {:?}", result);
         Ok(result)
     }
 
+    /// Deserialize `contents`, read from `path`, ignoring the values of the `locked` keys,
+    /// and record the values it sets.
+    fn parse(
+        contents: &str,
+        path: &Path,
+        locked: &[String],
+        sources: &mut ConfigSources,
+    ) -> Result<ConfigFile, toml::de::Error> {
+        let mut result: ConfigFile = toml::from_str(contents)?;
+
+        if let Ok(mut table) = toml::from_str::<toml::Table>(contents) {
+            if Self::remove_locked(&mut table, locked, &path.display().to_string()) {
+                result = toml::Value::Table(table.clone()).try_into()?;
+            }
+            sources.record(&table, &ConfigSource::File(path.to_path_buf()));
+        }
+
+        Ok(result)
+    }
+
+    /// Remove the `locked` keys (e.g. `misc.sudo_command`) from `table`, also when they are set in
+    /// override tables, and warn about each of them being ignored in `origin`.
+    ///
+    /// Returns whether any key was removed.
+    fn remove_locked(table: &mut toml::Table, locked: &[String], origin: &str) -> bool {
+        fn remove(table: &mut toml::Table, path: &[&str]) -> bool {
+            match path {
+                [] => false,
+                [key] => table.remove(*key).is_some(),
+                [section, rest @ ..] => match table.get_mut(*section) {
+                    Some(toml::Value::Table(table)) => remove(table, rest),
+                    _ => false,
+                },
+            }
+        }
+
+        let mut removed_any = false;
+        for key in locked {
+            let path: Vec<&str> = key.split('.').collect();
+            let mut removed = remove(table, &path);
+            for overrides in ["hosts", "os", "distro"] {
+                if let Some(toml::Value::Table(overrides)) = table.get_mut(overrides) {
+                    for (_, table) in overrides.iter_mut() {
+                        if let toml::Value::Table(table) = table {
+                            removed |= remove(table, &path);
+                        }
+                    }
+                }
+            }
+
+            if removed {
+                print_warning(t!(
+                    "`{key}` is locked by the system-wide configuration, ignoring it in {origin}",
+                    key = key,
+                    origin = origin
+                ));
+                removed_any = true;
+            }
+        }
+
+        removed_any
+    }
+
+    /// Serialize this configuration for a remote Topgrade, without the keys only making sense here:
+    /// `include` paths, the remote hosts and the locked keys.
+    fn to_remote_toml(&self) -> Result<String> {
+        let mut table = toml::Table::try_from(self)?;
+        for key in ["include", "remotes", "locked"] {
+            table.remove(key);
+        }
+        if let Some(toml::Value::Table(misc)) = table.get_mut("misc") {
+            misc.remove("remote_topgrades");
+        }
+
+        Ok(toml::to_string(&table)?)
+    }
+
+    /// Merge the `[os.<os>]`, `[distro.<distro>]` and `[hosts."<glob>"]` tables matching the
+    /// current machine on top of the rest of the configuration.
+    ///
+    /// More specific tables take precedence: hosts over distributions over operating systems.
+    /// Within `[hosts]`, later matching tables take precedence over earlier ones.
+    fn apply_overrides(mut self, hostname: Option<&str>, os: &str, distro: Option<&str>) -> ConfigFile {
+        let os_overrides = self.os.take().unwrap_or_default();
+        let distro_overrides = self.distro.take().unwrap_or_default();
+        let host_overrides = self.hosts.take().unwrap_or_default();
+
+        let matching = os_overrides
+            .into_iter()
+            .filter(|(name, _)| name == os)
+            .chain(
+                distro_overrides
+                    .into_iter()
+                    .filter(|(name, _)| Some(name.as_str()) == distro),
+            )
+            .chain(
+                host_overrides
+                    .into_iter()
+                    .filter(|(pattern, _)| hostname.is_some_and(|hostname| WildMatch::new(pattern).matches(hostname))),
+            );
+
+        let mut result = ConfigFile::default();
+        for (name, mut overrides) in matching.collect::<Vec<_>>().into_iter().rev() {
+            debug!("Applying configuration overrides for {name}");
+            if overrides.include.is_some()
+                || overrides.hosts.is_some()
+                || overrides.os.is_some()
+                || overrides.distro.is_some()
+            {
+                error!("`include`, `hosts`, `os` and `distro` are not supported in the overrides for {name}");
+                overrides.include = None;
+                overrides.hosts = None;
+                overrides.os = None;
+                overrides.distro = None;
+            }
+            result.merge(&mut overrides);
+        }
+        result.merge(&mut self);
+        result
+    }
+
+    /// Build the configuration layer set by `TOPGRADE__<SECTION>__<KEY>` environment variables.
+    ///
+    /// Values are parsed as TOML when possible (`true`, `240`, `["a", "b"]`), otherwise as a
+    /// string, or as a comma-separated list for keys that expect one. Invalid variables, and
+    /// variables setting `locked` keys, are reported and ignored.
+    fn from_env(
+        vars: impl IntoIterator<Item = (String, String)>,
+        locked: &[String],
+        sources: &mut ConfigSources,
+    ) -> ConfigFile {
+        let mut vars: Vec<(String, String)> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        vars.sort();
+
+        let mut result = toml::Table::new();
+        for (name, value) in vars {
+            match Self::env_var_table(&name, &value) {
+                Ok(mut table) => {
+                    if Self::remove_locked(&mut table, locked, &name) {
+                        continue;
+                    }
+                    sources.record(&table, &ConfigSource::Env(name));
+                    Self::merge_tables(&mut result, table);
+                }
+                Err(e) => error!("Ignoring environment variable {name}: {e}"),
+            }
+        }
+
+        let config = toml::Value::Table(result).try_into().unwrap_or_else(|e| {
+            error!("Failed to load configuration from environment variables: {e}");
+            Self::default()
+        });
+        debug!("Configuration from environment variables: {:?}", config);
+        config
+    }
+
+    /// The table setting the key named by the environment variable `name` to `value`.
+    fn env_var_table(name: &str, value: &str) -> Result<toml::Table> {
+        let path: Vec<String> = name[ENV_PREFIX.len()..].split("__").map(str::to_lowercase).collect();
+        if path.iter().any(String::is_empty) {
+            color_eyre::eyre::bail!("Expected a name like {ENV_PREFIX}<SECTION>__<KEY>");
+        }
+
+        let literal = toml::from_str::<toml::Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut table| table.remove("value"));
+        let list = value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| toml::Value::String(item.to_string()))
+            .collect();
+        let candidates = literal
+            .into_iter()
+            .chain([toml::Value::String(value.to_string()), toml::Value::Array(list)]);
+
+        let mut error = None;
+        for candidate in candidates {
+            let table = path.iter().rev().fold(candidate, |value, key| {
+                toml::Value::Table(toml::Table::from_iter([(key.clone(), value)]))
+            });
+            let toml::Value::Table(table) = table else {
+                unreachable!("`path` is not empty")
+            };
+            match toml::Value::Table(table.clone()).try_into::<Self>() {
+                Ok(_) => return Ok(table),
+                Err(e) => error = error.or(Some(e)),
+            }
+        }
+
+        Err(error.expect("there is always a candidate").into())
+    }
+
+    /// Recursively merge `right` into `left`, with `right` taking precedence.
+    fn merge_tables(left: &mut toml::Table, right: toml::Table) {
+        for (key, value) in right {
+            match (left.get_mut(&key), value) {
+                (Some(toml::Value::Table(left)), toml::Value::Table(right)) => Self::merge_tables(left, right),
+                (_, value) => {
+                    left.insert(key, value);
+                }
+            }
+        }
+    }
+
+    /// The JSON Schema of the configuration file, pretty-printed.
+    ///
+    /// Editors (e.g., through taplo) use it to validate `topgrade.toml` and to
+    /// complete keys, step names and enum values.
+    fn json_schema() -> Result<String> {
+        let schema = schemars::schema_for!(ConfigFile);
+        let mut schema = serde_json::to_string_pretty(&schema)?;
+        schema.push('\n');
+        Ok(schema)
+    }
+
     fn edit() -> Result<()> {
         let config_path = Self::ensure()?.0;
         debug!("Editing config file: {:?}", config_path);
@@ -829,6 +1576,24 @@ impl ConfigFile {
     }
 }
 
+/// Subcommands of `topgrade`. Without one, Topgrade performs the upgrade.
+#[derive(Subcommand, Debug)]
+pub enum SubCommand {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone, Copy)]
+pub enum ConfigCommand {
+    /// Print the JSON Schema of the configuration file
+    Schema,
+    /// Print the values set by environment variables and configuration files, highest precedence first
+    Sources,
+}
+
 // Command line arguments
 // TODO: i18n of clap currently not easily possible. Waiting for https://github.com/clap-rs/clap/issues/380
 // Tracking issue for i18n: https://github.com/topgrade-rs/topgrade/issues/859
@@ -931,6 +1696,10 @@ pub struct CommandLineArgs {
     #[arg(long = "remote-host-limit", value_name = "REGEX")]
     remote_host_limit: Option<Regex>,
 
+    /// Only run Topgrade on the remote hosts of this group
+    #[arg(long = "remote-group", value_name = "NAME")]
+    remote_group: Option<String>,
+
     /// Show the reason for skipped steps
     #[arg(long = "show-skipped")]
     show_skipped: bool,
@@ -964,6 +1733,9 @@ pub struct CommandLineArgs {
     /// Don't update Topgrade
     #[arg(long = "no-self-update")]
     pub no_self_update: bool,
+
+    #[command(subcommand)]
+    command: Option<SubCommand>,
 }
 
 fn env_args_parser(arg: &str) -> Result<(String, String)> {
@@ -986,6 +1758,10 @@ impl CommandLineArgs {
         &self.env
     }
 
+    pub fn config_command(&self) -> Option<ConfigCommand> {
+        self.command.as_ref().map(|SubCommand::Config { command }| *command)
+    }
+
     /// In Topgrade, filter directives come from 3 places:
     ///     1. CLI option `--log-filter`
     ///     2. Config file
@@ -1017,41 +1793,75 @@ impl CommandLineArgs {
 pub struct Config {
     opt: CommandLineArgs,
     config_file: ConfigFile,
+    /// The configuration files before applying the overrides, sent to the remote hosts
+    /// using this configuration so that they apply their own
+    remote_toml: Option<String>,
     allowed_steps: Vec<Step>,
+    sources: ConfigSources,
 }
 
 impl Config {
     /// Load the configuration.
     ///
     /// The function parses the command line arguments and reads the configuration file.
+    /// Values set through `TOPGRADE__<SECTION>__<KEY>` environment variables take
+    /// precedence over the configuration file, command line arguments over both.
     pub fn load(opt: CommandLineArgs) -> Result<Self> {
-        let config_directory = config_directory();
-        let config_file = if config_directory.is_dir() {
-            ConfigFile::read(opt.config.clone()).unwrap_or_else(|e| {
-                // Inform the user about errors when loading the configuration,
-                // but fallback to the default config to at least attempt to do something
-                error!("failed to load configuration: {e}");
-                ConfigFile::default()
-            })
-        } else {
-            debug!("Configuration directory {} does not exist", config_directory.display());
+        let mut file_sources = ConfigSources::default();
+        let file_config = ConfigFile::read(opt.config.clone(), &mut file_sources).unwrap_or_else(|e| {
+            // Inform the user about errors when loading the configuration,
+            // but fallback to the default config to at least attempt to do something
+            error!("failed to load configuration: {e}");
             ConfigFile::default()
-        };
+        });
+        let remote_toml = file_config
+            .to_remote_toml()
+            .map_err(|e| error!("Failed to serialize the configuration for the remote hosts: {e}"))
+            .ok();
+        let mut file_config =
+            file_config.apply_overrides(hostname().ok().as_deref(), env::consts::OS, Self::distribution_id());
+
+        let mut sources = ConfigSources::default();
+        let locked = file_config.locked.clone().unwrap_or_default();
+        let mut config_file = ConfigFile::from_env(env::vars(), &locked, &mut sources);
+        config_file.merge(&mut file_config);
+        sources.extend(file_sources);
 
         let allowed_steps = Self::allowed_steps(&opt, &config_file);
 
         Ok(Self {
             opt,
             config_file,
+            remote_toml,
             allowed_steps,
+            sources,
         })
     }
 
+    /// The id of the detected Linux distribution, used to match `[distro.<id>]` tables.
+    fn distribution_id() -> Option<&'static str> {
+        #[cfg(target_os = "linux")]
+        return crate::steps::linux::Distribution::detect().ok().map(|d| d.id());
+
+        #[cfg(not(target_os = "linux"))]
+        return None;
+    }
+
+    /// The configuration values set by the environment and the configuration files.
+    pub fn sources(&self) -> &ConfigSources {
+        &self.sources
+    }
+
     /// Launch an editor to edit the configuration
     pub fn edit() -> Result<()> {
         ConfigFile::edit()
     }
 
+    /// The JSON Schema of the configuration file
+    pub fn json_schema() -> Result<String> {
+        ConfigFile::json_schema()
+    }
+
     /// The list of commands to run before performing any step.
     pub fn pre_commands(&self) -> &Option<Commands> {
         &self.config_file.pre_commands
@@ -1062,65 +1872,196 @@ impl Config {
         &self.config_file.post_commands
     }
 
-    /// The list of custom steps.
-    pub fn commands(&self) -> &Option<Commands> {
-        &self.config_file.commands
+    /// The list of custom steps.
+    pub fn commands(&self) -> &Option<Commands> {
+        &self.config_file.commands
+    }
+
+    /// The list of additional named conda environments.
+    pub fn conda_env_names(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .conda
+            .as_ref()
+            .and_then(|conda| conda.env_names.as_ref())
+    }
+
+    /// The list of additional conda environment paths.
+    pub fn conda_env_paths(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .conda
+            .as_ref()
+            .and_then(|conda| conda.env_paths.as_ref())
+    }
+
+    /// The patterns of the additional git repositories to pull.
+    pub fn git_repos(&self) -> Vec<&String> {
+        match self.config_file.git.as_ref().and_then(|git| git.repos.as_ref()) {
+            Some(GitRepos::Patterns(patterns)) => patterns.iter().collect(),
+            Some(GitRepos::Settings(settings)) => settings.keys().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether to verify the signatures of the incoming commits of the git repository `repo`.
+    pub fn git_verify_signatures(&self, repo: &Path) -> bool {
+        self.git_repo_setting(repo, |settings| settings.verify_signatures)
+            .or_else(|| self.config_file.git.as_ref().and_then(|git| git.verify_signatures))
+            .unwrap_or(false)
+    }
+
+    /// Fingerprints of the keys trusted to sign the commits of the git repository `repo`.
+    pub fn git_trusted_keys(&self, repo: &Path) -> Vec<String> {
+        self.git_repo_setting(repo, |settings| settings.trusted_keys.clone())
+            .or_else(|| self.config_file.git.as_ref().and_then(|git| git.trusted_keys.clone()))
+            .unwrap_or_default()
+    }
+
+    /// The allowed signers file checking the SSH signatures of the commits of the git repository `repo`.
+    pub fn git_allowed_signers(&self, repo: &Path) -> Option<String> {
+        self.git_repo_setting(repo, |settings| settings.allowed_signers.clone())
+            .or_else(|| {
+                self.config_file
+                    .git
+                    .as_ref()
+                    .and_then(|git| git.allowed_signers.clone())
+            })
+    }
+
+    /// The command to run after the revision of the git repository `repo` changed.
+    pub fn git_on_change(&self, repo: &Path) -> Option<String> {
+        self.git_repo_setting(repo, |settings| settings.on_change.clone())
+    }
+
+    /// Directories to search for git repositories.
+    pub fn git_roots(&self) -> &[String] {
+        self.config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.roots.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// How deep to search the git roots for repositories.
+    pub fn git_max_depth(&self) -> usize {
+        self.config_file.git.as_ref().and_then(|git| git.max_depth).unwrap_or(3)
+    }
+
+    /// How long to reuse the repositories found in the git roots.
+    pub fn git_discovery_cache(&self) -> Duration {
+        let hours = self
+            .config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.discovery_cache_hours)
+            .unwrap_or(24);
+        Duration::from_secs(hours * 60 * 60)
+    }
+
+    /// A setting of the git repository `repo`, from the first `[git.repos]` pattern matching it
+    /// which sets it.
+    fn git_repo_setting<T>(&self, repo: &Path, setting: impl Fn(&GitRepoSettings) -> Option<T>) -> Option<T> {
+        let Some(GitRepos::Settings(settings)) = self.config_file.git.as_ref().and_then(|git| git.repos.as_ref())
+        else {
+            return None;
+        };
+
+        settings
+            .iter()
+            .filter(|(pattern, _)| {
+                let pattern = shellexpand::tilde(pattern);
+                let pattern = pattern.trim_end_matches(['/', '\\']);
+                Path::new(pattern) == repo || glob::Pattern::new(pattern).is_ok_and(|p| p.matches_path(repo))
+            })
+            .find_map(|(_, settings)| setting(settings))
+    }
+
+    /// How to update the git repository `repo`.
+    pub fn git_strategy(&self, repo: &Path) -> GitStrategy {
+        self.git_repo_setting(repo, |settings| settings.strategy)
+            .or_else(|| self.config_file.git.as_ref().and_then(|git| git.strategy))
+            .unwrap_or(if self.git_fetch_only() {
+                GitStrategy::Fetch
+            } else {
+                GitStrategy::FfOnly
+            })
     }
 
-    /// The list of additional named conda environments.
-    pub fn conda_env_names(&self) -> Option<&Vec<String>> {
-        self.config_file
-            .conda
-            .as_ref()
-            .and_then(|conda| conda.env_names.as_ref())
+    /// The container runtimes to update, with their settings: the `runtimes` entries, or the
+    /// `[containers]` settings. Unset entry settings fall back to the `[containers]` ones.
+    pub fn containers_runtimes(&self) -> Vec<ContainerRuntimeConfig> {
+        let default = Containers::default();
+        let containers = self.config_file.containers.as_ref().unwrap_or(&default);
+        let entries = containers
+            .runtimes
+            .clone()
+            .unwrap_or_else(|| vec![RuntimeEntry::default()]);
+
+        entries
+            .into_iter()
+            .map(|entry| ContainerRuntimeConfig {
+                name: entry.name,
+                runtime: entry.runtime.or(containers.runtime).unwrap_or_default(),
+                use_sudo: entry.use_sudo.or(containers.use_sudo).unwrap_or(false),
+                ignored_containers: entry
+                    .ignored_containers
+                    .or_else(|| containers.ignored_containers.clone())
+                    .unwrap_or_default(),
+                system_prune: entry.system_prune.or(containers.system_prune).unwrap_or(false),
+                mode: entry.mode.or(containers.mode).unwrap_or_default(),
+                include_labels: entry
+                    .include_labels
+                    .or_else(|| containers.include_labels.clone())
+                    .unwrap_or_default(),
+                exclude_labels: entry
+                    .exclude_labels
+                    .or_else(|| containers.exclude_labels.clone())
+                    .unwrap_or_default(),
+            })
+            .collect()
     }
 
-    /// The list of additional conda environment paths.
-    pub fn conda_env_paths(&self) -> Option<&Vec<String>> {
+    /// Compose files of the projects to update.
+    pub fn containers_compose_projects(&self) -> Option<&Vec<String>> {
         self.config_file
-            .conda
+            .containers
             .as_ref()
-            .and_then(|conda| conda.env_paths.as_ref())
-    }
-
-    /// The list of additional git repositories to pull.
-    pub fn git_repos(&self) -> Option<&Vec<String>> {
-        self.config_file.git.as_ref().and_then(|git| git.repos.as_ref())
+            .and_then(|containers| containers.compose_projects.as_ref())
     }
 
-    /// The list of docker/podman containers to ignore.
-    pub fn containers_ignored_tags(&self) -> Option<&Vec<String>> {
+    /// Whether to remove the containers of services no longer in the compose files.
+    pub fn containers_compose_remove_orphans(&self) -> bool {
         self.config_file
             .containers
             .as_ref()
-            .and_then(|containers| containers.ignored_containers.as_ref())
+            .and_then(|containers| containers.compose_remove_orphans)
+            .unwrap_or(false)
     }
 
-    /// The preferred runtime for container updates (podman / docker).
-    pub fn containers_runtime(&self) -> ContainerRuntime {
+    /// Patterns of the running containers to run Topgrade in.
+    pub fn containers_exec_targets(&self) -> &[String] {
         self.config_file
             .containers
             .as_ref()
-            .and_then(|containers| containers.runtime)
+            .and_then(|containers| containers.exec_targets.as_deref())
             .unwrap_or_default()
     }
 
-    /// Whether to run system prune for containers.
-    pub fn containers_system_prune(&self) -> bool {
+    /// Labels of the running containers to run Topgrade in.
+    pub fn containers_exec_labels(&self) -> &[String] {
         self.config_file
             .containers
             .as_ref()
-            .and_then(|containers| containers.system_prune)
-            .unwrap_or(false)
+            .and_then(|containers| containers.exec_labels.as_deref())
+            .unwrap_or_default()
     }
 
-    /// Whether to use sudo for container operations.
-    pub fn containers_use_sudo(&self) -> bool {
+    /// Steps to run in the containers.
+    pub fn containers_exec_only(&self) -> &[Step] {
         self.config_file
             .containers
             .as_ref()
-            .and_then(|containers| containers.use_sudo)
-            .unwrap_or(false)
+            .and_then(|containers| containers.exec_only.as_deref())
+            .unwrap_or(&[Step::System])
     }
 
     /// Tell whether the specified step should run.
@@ -1277,6 +2218,142 @@ impl Config {
             .and_then(|misc| misc.remote_topgrades.as_ref())
     }
 
+    /// Remote hosts to run Topgrade on: the `remote_topgrades` and the `[remotes]` hosts, or only the
+    /// hosts of the group selected with `--remote-group`.
+    pub fn remotes(&self) -> Result<Vec<Remote>> {
+        let mut inventory = Inventory::default();
+        if let Some(remotes) = &self.config_file.remotes {
+            inventory.hosts = remotes.hosts.clone();
+            inventory.groups = remotes.groups.clone();
+
+            if let Some(path) = &remotes.inventory {
+                let path = shellexpand::tilde(path).into_owned();
+                let contents = fs::read_to_string(&path).with_context(|| format!("Unable to read {path}"))?;
+                let mut file: Inventory =
+                    toml::from_str(&contents).with_context(|| format!("Failed to deserialize {path}"))?;
+                inventory.merge(&mut file);
+            }
+        }
+        let hosts = inventory.hosts.unwrap_or_default();
+
+        let names: Vec<&str> = match &self.opt.remote_group {
+            Some(group) => inventory
+                .groups
+                .as_ref()
+                .and_then(|groups| groups.get(group))
+                .ok_or_else(|| eyre!("Unknown remote group `{group}`"))?
+                .iter()
+                .map(String::as_str)
+                .collect(),
+            None => self
+                .remote_topgrades()
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .chain(hosts.keys().map(String::as_str))
+                .unique()
+                .collect(),
+        };
+
+        Ok(names
+            .into_iter()
+            .map(|name| self.remote(name, hosts.get(name)))
+            .collect())
+    }
+
+    /// Resolve the settings of the remote host `name`, falling back to the `[misc]` ones.
+    fn remote(&self, name: &str, host: Option<&RemoteHost>) -> Remote {
+        let default = RemoteHost::default();
+        let host = host.unwrap_or(&default);
+
+        let hostname = host.hostname.as_deref().unwrap_or(name);
+        let destination = match &host.user {
+            Some(user) => format!("{user}@{hostname}"),
+            None => hostname.to_string(),
+        };
+
+        let mut ssh_arguments = Vec::new();
+        if let Some(port) = host.port {
+            ssh_arguments.extend(["-p".to_string(), port.to_string()]);
+        }
+        if let Some(jump_host) = &host.jump_host {
+            ssh_arguments.extend(["-J".to_string(), jump_host.clone()]);
+        }
+        for arguments in [self.ssh_arguments(), host.ssh_arguments.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            ssh_arguments.extend(arguments.split_whitespace().map(String::from));
+        }
+
+        let mut topgrade_arguments: Vec<String> = host
+            .topgrade_arguments
+            .iter()
+            .flat_map(|arguments| arguments.split_whitespace().map(String::from))
+            .collect();
+        if host.assume_yes.unwrap_or(false) {
+            topgrade_arguments.push("--yes".to_string());
+        }
+
+        Remote {
+            name: name.to_string(),
+            destination,
+            ssh_arguments,
+            topgrade_path: host
+                .topgrade_path
+                .clone()
+                .unwrap_or_else(|| self.remote_topgrade_path().to_string()),
+            topgrade_arguments,
+            bootstrap: host.bootstrap.or(self.remote_bootstrap()).unwrap_or(false),
+            config: host.config.clone().or_else(|| self.remote_config_option().cloned()),
+        }
+    }
+
+    /// The configuration to send to `remote` for the run, if any: this machine's configuration
+    /// files for `local`, with their `[os]`, `[distro]` and `[hosts]` overrides left for the remote
+    /// host to apply, or the contents of the given file.
+    pub fn remote_config(&self, remote: &Remote) -> Result<Option<String>> {
+        match remote.config.as_deref() {
+            None => Ok(None),
+            Some("local") => self
+                .remote_toml
+                .clone()
+                .map(Some)
+                .ok_or_else(|| eyre!("Failed to serialize the configuration")),
+            Some(path) => {
+                let path = shellexpand::tilde(path).into_owned();
+                let contents = fs::read_to_string(&path).with_context(|| format!("Unable to read {path}"))?;
+                Ok(Some(contents))
+            }
+        }
+    }
+
+    /// Whether to install this Topgrade on the remote hosts where it is missing or older
+    fn remote_bootstrap(&self) -> Option<bool> {
+        self.config_file.misc.as_ref().and_then(|misc| misc.remote_bootstrap)
+    }
+
+    /// The configuration to send to the remote hosts
+    fn remote_config_option(&self) -> Option<&String> {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.remote_config.as_ref())
+    }
+
+    /// How many remote hosts to run Topgrade on at once
+    pub fn remote_parallel(&self) -> usize {
+        if self.run_in_tmux() {
+            return 1;
+        }
+
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.remote_parallel)
+            .unwrap_or(1)
+    }
+
     /// Path to Topgrade executable used for all remote hosts
     pub fn remote_topgrade_path(&self) -> &str {
         self.config_file
@@ -1721,11 +2798,124 @@ impl Config {
         self.config_file.distrobox.as_ref().and_then(|r| r.containers.as_ref())
     }
 
+    /// How to update the boxes of Distrobox
+    pub fn distrobox_mode(&self) -> DistroboxMode {
+        self.config_file
+            .distrobox
+            .as_ref()
+            .and_then(|r| r.mode)
+            .unwrap_or_default()
+    }
+
+    /// Toolboxes not to update
+    pub fn toolbx_exclude(&self) -> &[String] {
+        self.config_file
+            .toolbx
+            .as_ref()
+            .and_then(|toolbx| toolbx.exclude.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// How to run Topgrade in the toolbox `name`.
+    pub fn toolbox(&self, name: &str) -> Result<Toolbox> {
+        let toolbx = self.config_file.toolbx.as_ref();
+        let settings = toolbx
+            .and_then(|toolbx| toolbx.containers.as_ref())
+            .and_then(|containers| containers.get(name));
+
+        let only = settings
+            .and_then(|settings| settings.only.clone())
+            .or_else(|| toolbx.and_then(|toolbx| toolbx.only.clone()));
+
+        let mut arguments = Vec::new();
+        for args in [
+            toolbx.and_then(|toolbx| toolbx.arguments.as_ref()),
+            settings.and_then(|settings| settings.arguments.as_ref()),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            arguments.extend(
+                shell_words::split(args)
+                    .with_context(|| format!("Failed to parse the arguments of toolbox `{name}`: `{args}`"))?,
+            );
+        }
+
+        Ok(Toolbox { only, arguments })
+    }
+
+    /// Steps to run in the boxes of Distrobox
+    pub fn distrobox_only(&self) -> Option<&Vec<Step>> {
+        self.config_file.distrobox.as_ref().and_then(|r| r.only.as_ref())
+    }
+
     /// Concurrency limit for git
     pub fn git_concurrency_limit(&self) -> Option<usize> {
         self.config_file.git.as_ref().and_then(|git| git.max_concurrency)
     }
 
+    /// Root directories of the chroots to update
+    pub fn chroot_directories(&self) -> &[String] {
+        self.config_file
+            .chroot
+            .as_ref()
+            .and_then(|chroot| chroot.directories.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// How to run commands in the chroots
+    pub fn chroot_method(&self) -> ChrootMethod {
+        self.config_file
+            .chroot
+            .as_ref()
+            .and_then(|chroot| chroot.method)
+            .unwrap_or_default()
+    }
+
+    /// Incus instances to update
+    pub fn incus_instances(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .incus
+            .as_ref()
+            .and_then(|incus| incus.instances.as_ref())
+    }
+
+    /// Incus instances not to update
+    pub fn incus_exclude(&self) -> &[String] {
+        self.config_file
+            .incus
+            .as_ref()
+            .and_then(|incus| incus.exclude.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Whether to start the stopped Incus instances for the update
+    pub fn incus_power_on(&self) -> bool {
+        self.config_file
+            .incus
+            .as_ref()
+            .and_then(|incus| incus.power_on)
+            .unwrap_or(false)
+    }
+
+    /// How to update the Incus instances
+    pub fn incus_mode(&self) -> IncusMode {
+        self.config_file
+            .incus
+            .as_ref()
+            .and_then(|incus| incus.mode)
+            .unwrap_or_default()
+    }
+
+    /// Steps to run in the Incus instances
+    pub fn incus_only(&self) -> &[Step] {
+        self.config_file
+            .incus
+            .as_ref()
+            .and_then(|incus| incus.only.as_deref())
+            .unwrap_or(&[Step::System])
+    }
+
     /// Determine whether we should power on vagrant boxes
     pub fn vagrant_power_on(&self) -> Option<bool> {
         self.config_file.vagrant.as_ref().and_then(|vagrant| vagrant.power_on)
@@ -2257,6 +3447,7 @@ mod test {
     use crate::config::*;
     use color_eyre::eyre::eyre;
     use merge2::Merge;
+    use strum::EnumCount;
 
     /// Regression test: verify that `overwrite_none` merge strategy preserves
     /// existing (left) values and only fills in `None` fields from right.
@@ -2268,12 +3459,14 @@ mod test {
             runtime: Some(ContainerRuntime::Podman),
             system_prune: Some(false),
             use_sudo: None,
+            ..Default::default()
         };
         let mut right = Containers {
             ignored_containers: None,
             runtime: Some(ContainerRuntime::Docker),
             system_prune: None,
             use_sudo: Some(true),
+            ..Default::default()
         };
         left.merge(&mut right);
 
@@ -2292,6 +3485,239 @@ mod test {
         assert!(toml::from_str::<ConfigFile>(str).is_ok());
     }
 
+    /// `config.schema.json` must match the schema generated from `ConfigFile`.
+    /// Regenerate it with `cargo run -- config schema > config.schema.json`.
+    #[test]
+    fn test_json_schema_is_up_to_date() {
+        let schema = ConfigFile::json_schema().unwrap();
+
+        assert!(
+            schema == include_str!("../config.schema.json"),
+            "config.schema.json is outdated, run `cargo run -- config schema > config.schema.json`"
+        );
+    }
+
+    /// Every step accepted in the configuration is listed in the schema.
+    #[test]
+    fn test_json_schema_lists_all_steps() {
+        let schema: serde_json::Value = serde_json::from_str(&ConfigFile::json_schema().unwrap()).unwrap();
+        let steps = schema["$defs"]["Step"]["enum"]
+            .as_array()
+            .expect("Step should be an enum");
+
+        assert_eq!(steps.len(), Step::COUNT);
+        for step in steps {
+            let step = step.as_str().unwrap();
+            assert!(
+                toml::Value::String(step.to_string()).try_into::<Step>().is_ok(),
+                "{step}"
+            );
+        }
+    }
+
+    fn env(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string()))
+    }
+
+    #[test]
+    fn test_env_overrides_parse_values() {
+        let mut sources = ConfigSources::default();
+        let config_file = ConfigFile::from_env(
+            env(&[
+                ("TOPGRADE__MISC__DISABLE", "containers, flatpak"),
+                ("TOPGRADE__MISC__ASSUME_YES", "true"),
+                ("TOPGRADE__MISC__SUDO_LOOP_INTERVAL", "30"),
+                ("TOPGRADE__MISC__BASHIT_BRANCH", "true"),
+                ("TOPGRADE__LINUX__APT_ARGUMENTS", "--no-install-recommends -q"),
+                ("TOPGRADE_PREFIX", "ignored"),
+            ]),
+            &[],
+            &mut sources,
+        );
+
+        let misc = config_file.misc.unwrap();
+        assert_eq!(misc.disable, Some(vec![Step::Containers, Step::Flatpak]));
+        assert_eq!(misc.assume_yes, Some(true));
+        assert_eq!(misc.sudo_loop_interval, Some(30));
+        // Falls back to a string for string keys
+        assert_eq!(misc.bashit_branch.as_deref(), Some("true"));
+        assert_eq!(
+            config_file.linux.unwrap().apt_arguments.as_deref(),
+            Some("--no-install-recommends -q")
+        );
+        assert_eq!(sources.0.len(), 5);
+    }
+
+    #[test]
+    fn test_env_overrides_ignore_invalid_variables() {
+        let mut sources = ConfigSources::default();
+        let config_file = ConfigFile::from_env(
+            env(&[
+                ("TOPGRADE__MISC__NOT_A_KEY", "1"),
+                ("TOPGRADE__MISC__DISABLE", "not_a_step"),
+                ("TOPGRADE__MISC", "true"),
+                ("TOPGRADE__MISC__CLEANUP", "true"),
+            ]),
+            &[],
+            &mut sources,
+        );
+
+        assert_eq!(config_file.misc.unwrap().cleanup, Some(true));
+        assert_eq!(sources.0.len(), 1);
+    }
+
+    #[test]
+    fn test_env_overrides_take_precedence_over_file() {
+        let mut sources = ConfigSources::default();
+        let mut config_file = ConfigFile::from_env(
+            env(&[("TOPGRADE__MISC__CLEANUP", "false"), ("TOPGRADE__MISC__DISABLE", "vim")]),
+            &[],
+            &mut sources,
+        );
+        let mut file: ConfigFile = toml::from_str("[misc]\ncleanup = true\ndisable = [\"emacs\"]").unwrap();
+        config_file.merge(&mut file);
+
+        let misc = config_file.misc.unwrap();
+        assert_eq!(misc.cleanup, Some(false));
+        assert_eq!(misc.disable, Some(vec![Step::Emacs, Step::Vim]));
+    }
+
+    #[test]
+    fn test_env_overrides_ignore_locked_keys() {
+        let mut sources = ConfigSources::default();
+        let config_file = ConfigFile::from_env(
+            env(&[
+                ("TOPGRADE__MISC__SUDO_COMMAND", "doas"),
+                ("TOPGRADE__MISC__CLEANUP", "true"),
+            ]),
+            &["misc.sudo_command".to_string()],
+            &mut sources,
+        );
+
+        let misc = config_file.misc.unwrap();
+        assert!(misc.sudo_command.is_none());
+        assert_eq!(misc.cleanup, Some(true));
+        assert_eq!(sources.0.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_ignores_locked_keys() {
+        let mut sources = ConfigSources::default();
+        let contents = r#"
+[misc]
+sudo_command = "doas"
+cleanup = true
+
+[hosts."*".misc]
+sudo_command = "doas"
+
+[git]
+repos = ["~/src/*"]
+"#;
+        let config_file = ConfigFile::parse(
+            contents,
+            Path::new("topgrade.toml"),
+            &["misc.sudo_command".to_string(), "git".to_string()],
+            &mut sources,
+        )
+        .unwrap();
+
+        let misc = config_file.misc.unwrap();
+        assert!(misc.sudo_command.is_none());
+        assert_eq!(misc.cleanup, Some(true));
+        assert!(config_file.git.is_none());
+        let hosts = config_file.hosts.unwrap();
+        assert!(hosts["*"].misc.as_ref().unwrap().sudo_command.is_none());
+        assert_eq!(sources.0.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_system_configuration() {
+        let directory = tempfile::tempdir().unwrap();
+        fs::create_dir_all(directory.path().join("topgrade.d")).unwrap();
+        fs::create_dir_all(directory.path().join("topgrade")).unwrap();
+        fs::write(
+            directory.path().join("topgrade.d/10-disable.toml"),
+            "locked = [\"misc.disable\"]\n[misc]\ndisable = [\"vim\"]",
+        )
+        .unwrap();
+        fs::write(directory.path().join("topgrade.d/20-broken.toml"), "[misc").unwrap();
+        fs::write(
+            directory.path().join("topgrade/topgrade.toml"),
+            "[misc]\ncleanup = true",
+        )
+        .unwrap();
+
+        let mut sources = ConfigSources::default();
+        let config_file = ConfigFile::read_system(directory.path(), &mut sources);
+
+        assert_eq!(config_file.locked, Some(vec!["misc.disable".to_string()]));
+        let misc = config_file.misc.unwrap();
+        assert_eq!(misc.disable, Some(vec![Step::Vim]));
+        assert_eq!(misc.cleanup, Some(true));
+    }
+
+    #[test]
+    fn test_overrides_apply_matching_tables() {
+        let config_file: ConfigFile = toml::from_str(
+            r#"
+[misc]
+disable = ["emacs"]
+cleanup = false
+assume_yes = false
+
+[os.linux.misc]
+cleanup = true
+sudo_loop = true
+
+[os.windows.misc]
+cleanup = false
+
+[distro.nixos.misc]
+sudo_loop = false
+
+[hosts."build-*".misc]
+disable = ["containers"]
+assume_yes = true
+
+[hosts."laptop".misc]
+assume_yes = true
+"#,
+        )
+        .unwrap();
+
+        let misc = config_file
+            .apply_overrides(Some("build-01"), "linux", Some("nixos"))
+            .misc
+            .unwrap();
+        assert_eq!(misc.disable, Some(vec![Step::Emacs, Step::Containers]));
+        assert_eq!(misc.cleanup, Some(true));
+        assert_eq!(misc.sudo_loop, Some(false));
+        assert_eq!(misc.assume_yes, Some(true));
+    }
+
+    #[test]
+    fn test_overrides_ignore_non_matching_tables() {
+        let config_file: ConfigFile = toml::from_str(
+            r#"
+[misc]
+cleanup = false
+
+[os.macos.misc]
+cleanup = true
+
+[hosts."build-*".misc]
+cleanup = true
+"#,
+        )
+        .unwrap();
+
+        let config_file = config_file.apply_overrides(None, "linux", None);
+        assert_eq!(config_file.misc.unwrap().cleanup, Some(false));
+        assert!(config_file.hosts.is_none() && config_file.os.is_none());
+    }
+
     /// `topgrade.d` must not be auto-created, only read when present.
     /// See: https://github.com/topgrade-rs/topgrade/issues/624
     #[test]
@@ -2319,7 +3745,9 @@ mod test {
         Config {
             opt: CommandLineArgs::parse_from::<_, String>([]),
             config_file: ConfigFile::default(),
+            remote_toml: None,
             allowed_steps: Vec::new(),
+            sources: ConfigSources::default(),
         }
     }
 
@@ -2362,6 +3790,262 @@ mod test {
         assert!(!config.should_execute_remote(Ok("hostname".to_string()), "user@remote_hostname"));
     }
 
+    const REMOTES: &str = r#"
+[misc]
+remote_topgrades = ["toothless", "pi"]
+ssh_arguments = "-o ConnectTimeout=2"
+
+[remotes.hosts.pi]
+hostname = "pi.local"
+user = "admin"
+port = 2222
+jump_host = "bastion"
+topgrade_path = "~/.cargo/bin/topgrade"
+topgrade_arguments = "--only system"
+assume_yes = true
+bootstrap = true
+config = "local"
+
+[remotes.hosts.nas]
+
+[remotes.groups]
+servers = ["pi", "nas"]
+"#;
+
+    #[test]
+    fn test_remotes_resolve_host_settings() {
+        let mut config = config();
+        config.config_file = toml::from_str(REMOTES).unwrap();
+
+        let remotes = config.remotes().unwrap();
+        assert_eq!(
+            remotes,
+            vec![
+                Remote {
+                    name: "toothless".to_string(),
+                    destination: "toothless".to_string(),
+                    ssh_arguments: vec!["-o".to_string(), "ConnectTimeout=2".to_string()],
+                    topgrade_path: "topgrade".to_string(),
+                    topgrade_arguments: Vec::new(),
+                    bootstrap: false,
+                    config: None,
+                },
+                Remote {
+                    name: "pi".to_string(),
+                    destination: "admin@pi.local".to_string(),
+                    ssh_arguments: ["-p", "2222", "-J", "bastion", "-o", "ConnectTimeout=2"]
+                        .map(String::from)
+                        .to_vec(),
+                    topgrade_path: "~/.cargo/bin/topgrade".to_string(),
+                    topgrade_arguments: ["--only", "system", "--yes"].map(String::from).to_vec(),
+                    bootstrap: true,
+                    config: Some("local".to_string()),
+                },
+                Remote {
+                    name: "nas".to_string(),
+                    destination: "nas".to_string(),
+                    ssh_arguments: vec!["-o".to_string(), "ConnectTimeout=2".to_string()],
+                    topgrade_path: "topgrade".to_string(),
+                    topgrade_arguments: Vec::new(),
+                    bootstrap: false,
+                    config: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remotes_select_group() {
+        let mut config = config();
+        config.config_file = toml::from_str(REMOTES).unwrap();
+        config.opt = CommandLineArgs::parse_from(["topgrade", "--remote-group", "servers"]);
+
+        let names: Vec<String> = config.remotes().unwrap().into_iter().map(|r| r.name).collect();
+        assert_eq!(names, ["pi", "nas"]);
+    }
+
+    #[test]
+    fn test_remotes_unknown_group() {
+        let mut config = config();
+        config.config_file = toml::from_str(REMOTES).unwrap();
+        config.opt = CommandLineArgs::parse_from(["topgrade", "--remote-group", "desktops"]);
+
+        let error = config.remotes().unwrap_err();
+        assert_eq!(error.to_string(), "Unknown remote group `desktops`");
+    }
+
+    #[test]
+    fn test_remote_config_local() {
+        let mut config = config();
+        config.config_file = toml::from_str(REMOTES).unwrap();
+        config.remote_toml = config.config_file.to_remote_toml().ok();
+        let remotes = config.remotes().unwrap();
+
+        let contents = config.remote_config(&remotes[1]).unwrap().unwrap();
+        let remote_config: ConfigFile = toml::from_str(&contents).unwrap();
+        let misc = remote_config.misc.unwrap();
+        assert_eq!(misc.ssh_arguments.as_deref(), Some("-o ConnectTimeout=2"));
+        assert!(misc.remote_topgrades.is_none());
+        assert!(remote_config.remotes.is_none());
+
+        assert!(config.remote_config(&remotes[0]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_remote_config_keeps_overrides() {
+        let config_file: ConfigFile = toml::from_str(
+            r#"
+[misc]
+cleanup = false
+
+[remotes.hosts.pi]
+config = "local"
+
+[hosts."here".misc]
+cleanup = true
+
+[hosts."pi".misc]
+assume_yes = true
+"#,
+        )
+        .unwrap();
+        let mut config = config();
+        config.remote_toml = config_file.to_remote_toml().ok();
+        config.config_file = config_file.apply_overrides(Some("here"), "linux", None);
+        assert_eq!(config.config_file.misc.as_ref().unwrap().cleanup, Some(true));
+
+        let remotes = config.remotes().unwrap();
+        let contents = config.remote_config(&remotes[0]).unwrap().unwrap();
+        let remote_config: ConfigFile = toml::from_str(&contents).unwrap();
+        let hosts = remote_config.hosts.as_ref().unwrap();
+        assert_eq!(hosts["pi"].misc.as_ref().unwrap().assume_yes, Some(true));
+        assert!(hosts.contains_key("here"));
+
+        let remote_config = remote_config.apply_overrides(Some("pi"), "linux", None);
+        let misc = remote_config.misc.unwrap();
+        assert_eq!(misc.cleanup, Some(false));
+        assert_eq!(misc.assume_yes, Some(true));
+    }
+
+    #[test]
+    fn test_containers_runtimes() {
+        let mut config = config();
+        assert_eq!(config.containers_runtimes().len(), 1);
+
+        config.config_file = toml::from_str(
+            r#"
+[containers]
+ignored_containers = ["docker.io*"]
+system_prune = true
+
+exclude_labels = ["org.example.pinned"]
+
+[[containers.runtimes]]
+runtime = "podman"
+mode = "auto_update"
+
+[[containers.runtimes]]
+runtime = "docker"
+use_sudo = true
+ignored_containers = []
+"#,
+        )
+        .unwrap();
+
+        let runtimes = config.containers_runtimes();
+        assert_eq!(runtimes.len(), 2);
+        assert_eq!(runtimes[0].name(), "podman");
+        assert_eq!(runtimes[0].ignored_containers, ["docker.io*"]);
+        assert!(runtimes[0].system_prune);
+        assert_eq!(runtimes[0].mode, ContainersMode::AutoUpdate);
+        assert_eq!(runtimes[1].name(), "docker (sudo)");
+        assert_eq!(runtimes[1].mode, ContainersMode::Pull);
+        assert_eq!(runtimes[1].exclude_labels, ["org.example.pinned"]);
+        assert!(runtimes[1].ignored_containers.is_empty());
+    }
+
+    #[test]
+    fn test_git_strategy() {
+        let mut config = config();
+        assert_eq!(config.git_strategy(Path::new("/src/a")), GitStrategy::FfOnly);
+
+        config.config_file = toml::from_str(
+            r#"
+[git]
+strategy = "rebase"
+
+[git.repos."/src/*"]
+
+[git.repos."/home/user/dotfiles/"]
+strategy = "autostash-rebase"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.git_repos(), ["/src/*", "/home/user/dotfiles/"]);
+        assert_eq!(config.git_strategy(Path::new("/src/a")), GitStrategy::Rebase);
+        assert_eq!(
+            config.git_strategy(Path::new("/home/user/dotfiles")),
+            GitStrategy::AutostashRebase
+        );
+
+        config.config_file = toml::from_str("[git]\nfetch_only = true\nrepos = [\"/src/*\"]").unwrap();
+        assert_eq!(config.git_strategy(Path::new("/src/a")), GitStrategy::Fetch);
+    }
+
+    #[test]
+    fn test_merge_git_repos() {
+        let mut left = Some(GitRepos::Patterns(vec!["~/a".to_string()]));
+        merge_git_repos(&mut left, &mut Some(GitRepos::Patterns(vec!["~/b".to_string()])));
+        assert!(matches!(&left, Some(GitRepos::Patterns(patterns)) if patterns == &["~/b", "~/a"]));
+
+        let mut right = Some(GitRepos::Settings(IndexMap::from([(
+            "~/a".to_string(),
+            GitRepoSettings {
+                strategy: Some(GitStrategy::Rebase),
+                ..Default::default()
+            },
+        )])));
+        merge_git_repos(&mut left, &mut right);
+        let Some(GitRepos::Settings(settings)) = left else {
+            panic!("expected settings");
+        };
+        assert_eq!(settings.len(), 2);
+        assert_eq!(settings["~/a"].strategy, Some(GitStrategy::Rebase));
+    }
+
+    #[test]
+    fn test_toolbox() {
+        let mut config = config();
+        assert_eq!(
+            config.toolbox("fedora").unwrap(),
+            Toolbox {
+                only: None,
+                arguments: Vec::new()
+            }
+        );
+
+        config.config_file = toml::from_str(
+            r#"
+[toolbx]
+only = ["system"]
+arguments = "--cleanup"
+
+[toolbx.containers.dev]
+only = ["system", "pipx"]
+arguments = "--disable 'cargo'"
+"#,
+        )
+        .unwrap();
+
+        let toolbox = config.toolbox("fedora").unwrap();
+        assert_eq!(toolbox.only, Some(vec![Step::System]));
+        assert_eq!(toolbox.arguments, ["--cleanup"]);
+
+        let toolbox = config.toolbox("dev").unwrap();
+        assert_eq!(toolbox.only, Some(vec![Step::System, Step::Pipx]));
+        assert_eq!(toolbox.arguments, ["--cleanup", "--disable", "cargo"]);
+    }
+
     /// Ensure that custom commands are stored in insertion order.
     #[test]
     fn test_custom_commands_order() {
@@ -2396,7 +4080,9 @@ x = "cmd_x"
         Config {
             opt: CommandLineArgs::parse_from::<_, String>([]),
             config_file: toml::from_str(toml_str).expect("toml parse error"),
+            remote_toml: None,
             allowed_steps: Vec::new(),
+            sources: ConfigSources::default(),
         }
     }
 
@@ -150,6 +150,36 @@ impl Executor {
         self
     }
 
+    /// See `std::process::Command::stdout`
+    pub fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Executor {
+        let stdio = cfg.into();
+        match self {
+            Executor::Wet(c) | Executor::Damp(c) => {
+                c.stdout(stdio);
+            }
+            Executor::Dry(c) => {
+                c.stdout = Some(stdio);
+            }
+        }
+
+        self
+    }
+
+    /// See `std::process::Command::stderr`
+    pub fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Executor {
+        let stdio = cfg.into();
+        match self {
+            Executor::Wet(c) | Executor::Damp(c) => {
+                c.stderr(stdio);
+            }
+            Executor::Dry(c) => {
+                c.stderr = Some(stdio);
+            }
+        }
+
+        self
+    }
+
     #[allow(dead_code)]
     /// See `std::process::Command::remove_env`
     pub fn env_remove<K>(&mut self, key: K) -> &mut Executor
@@ -281,6 +311,8 @@ pub struct DryCommand {
     envs: Vec<(OsString, OsString)>,
     env_removals: Vec<OsString>,
     stdin: Option<Stdio>,
+    stdout: Option<Stdio>,
+    stderr: Option<Stdio>,
 }
 
 impl DryCommand {
@@ -292,6 +324,8 @@ impl DryCommand {
             envs: Vec::new(),
             env_removals: Vec::new(),
             stdin: None,
+            stdout: None,
+            stderr: None,
         }
     }
 
@@ -312,6 +346,12 @@ impl DryCommand {
         if let Some(stdin) = self.stdin {
             cmd.stdin(stdin);
         }
+        if let Some(stdout) = self.stdout {
+            cmd.stdout(stdout);
+        }
+        if let Some(stderr) = self.stderr {
+            cmd.stderr(stderr);
+        }
         cmd
     }
 }
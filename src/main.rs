@@ -20,7 +20,7 @@ use rust_i18n::{i18n, t};
 use std::sync::LazyLock;
 use tracing::debug;
 
-use self::config::{CommandLineArgs, Config};
+use self::config::{CommandLineArgs, Config, ConfigCommand};
 use self::error::StepFailed;
 use self::runner::StepResult;
 use self::steps::{remote::*, *};
@@ -107,6 +107,14 @@ fn run() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(command) = opt.config_command() {
+        match command {
+            ConfigCommand::Schema => print!("{}", Config::json_schema()?),
+            ConfigCommand::Sources => print!("{}", Config::load(opt)?.sources()),
+        }
+        return Ok(());
+    }
+
     let config = Config::load(opt)?;
     // Update the logger with the full filter directives.
     update_tracing(&reload_handle, &config.tracing_filter_directives())?;
@@ -227,6 +235,11 @@ fn run() -> Result<()> {
                 skipped_missing_sudo = true;
             }
             print_result(key, result);
+            if let Some(nested) = runner.nested_report(key) {
+                for (key, result) in nested {
+                    print_result(format!("  {key}"), result);
+                }
+            }
         }
 
         if skipped_missing_sudo {
@@ -262,6 +275,10 @@ fn run() -> Result<()> {
         }
     }
 
+    if env::var_os(ssh::REPORT_ENV).is_some() {
+        ssh::print_report(report, runner.details());
+    }
+
     #[cfg(target_os = "linux")]
     if config.show_distribution_summary()
         && let Ok(distribution) = &distribution
@@ -1,6 +1,8 @@
 use color_eyre::eyre::{Result, WrapErr};
 use rust_i18n::t;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::fmt::Debug;
 use std::io;
 use tracing::debug;
@@ -11,6 +13,7 @@ use crate::execution_context::ExecutionContext;
 use crate::step::Step;
 use crate::terminal::{ShouldRetry, print_error, print_warning, should_retry};
 
+#[derive(Debug, Serialize, Deserialize)]
 pub enum StepResult {
     Success,
     Failure,
@@ -36,11 +39,15 @@ enum RetryDecision {
     Continue(StepResult),
 }
 
-type Report<'a> = Vec<(Cow<'a, str>, StepResult)>;
+pub type Report<'a> = Vec<(Cow<'a, str>, StepResult)>;
 
 pub struct Runner<'a> {
     ctx: &'a ExecutionContext<'a>,
     report: Report<'a>,
+    /// Reports of the runs of Topgrade on remote hosts, by step key.
+    nested_reports: Vec<(Cow<'a, str>, Report<'static>)>,
+    /// What some steps updated, by step key, for the machine-readable report.
+    details: Vec<(Cow<'a, str>, serde_json::Value)>,
 }
 
 impl<'a> Runner<'a> {
@@ -48,6 +55,8 @@ impl<'a> Runner<'a> {
         Runner {
             ctx,
             report: Vec::new(),
+            nested_reports: Vec::new(),
+            details: Vec::new(),
         }
     }
 
@@ -176,7 +185,60 @@ impl<'a> Runner<'a> {
         Ok(())
     }
 
+    /// Run `func` to get the items a step updates one by one, each under its own key.
+    ///
+    /// A failure of `func` is handled like a failure of the step `key`, retrying `func` when
+    /// asked to, and gives no items. Success is not reported, the items being reported instead.
+    pub fn collect<K, T, F>(&mut self, step: Step, key: K, func: F) -> Result<Vec<T>>
+    where
+        K: Into<Cow<'a, str>> + Debug,
+        F: Fn() -> Result<Vec<T>>,
+    {
+        if !self.ctx.config().should_run(step) {
+            return Ok(Vec::new());
+        }
+
+        let error = match func() {
+            Ok(items) => return Ok(items),
+            Err(e) => RefCell::new(Some(e)),
+        };
+        let items = RefCell::new(Vec::new());
+        self.execute(step, key, || {
+            // The first attempt reports the error of the call above, the retries call `func` again
+            let first_error = error.borrow_mut().take();
+            *items.borrow_mut() = match first_error {
+                Some(e) => return Err(e),
+                None => func()?,
+            };
+            Ok(())
+        })?;
+
+        Ok(items.into_inner())
+    }
+
     pub fn report(&self) -> &Report<'_> {
         &self.report
     }
+
+    /// Attach the report of a run of Topgrade elsewhere, e.g. on a remote host, to the step `key`.
+    pub fn push_nested_report<K: Into<Cow<'a, str>>>(&mut self, key: K, report: Report<'static>) {
+        self.nested_reports.push((key.into(), report));
+    }
+
+    /// Attach the details of what the step `key` updated, e.g. the states of the Git repositories,
+    /// to the machine-readable report.
+    pub fn push_details<K: Into<Cow<'a, str>>>(&mut self, key: K, details: serde_json::Value) {
+        self.details.push((key.into(), details));
+    }
+
+    pub fn details(&self) -> &[(Cow<'a, str>, serde_json::Value)] {
+        &self.details
+    }
+
+    /// The report attached to the step `key` with `push_nested_report()`, if any.
+    pub fn nested_report(&self, key: &str) -> Option<&Report<'static>> {
+        self.nested_reports
+            .iter()
+            .find_map(|(k, report)| (k == key).then_some(report))
+    }
 }
@@ -1,14 +1,21 @@
+use std::cell::{Cell, RefCell};
+
+#[cfg(target_os = "linux")]
+use crate::config::DistroboxMode;
 use crate::execution_context::ExecutionContext;
 use crate::runner::Runner;
 use clap::ValueEnum;
 use color_eyre::Result;
 #[cfg(target_os = "linux")]
 use rust_i18n::t;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use strum::{EnumCount, EnumIter, EnumString, VariantNames};
 
 #[cfg(feature = "self-update")]
 use crate::self_update;
+#[cfg(target_os = "linux")]
+use crate::steps::remote::incus;
 use crate::steps::remote::vagrant;
 use crate::steps::*;
 use crate::utils::hostname;
@@ -16,7 +23,20 @@ use crate::utils::hostname;
 pub const DEPRECATED_STEPS: [Step; 1] = [Step::NixHelper];
 
 #[derive(
-    ValueEnum, EnumString, VariantNames, Debug, Clone, PartialEq, Eq, Hash, Deserialize, EnumIter, Copy, EnumCount,
+    ValueEnum,
+    EnumString,
+    VariantNames,
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    Deserialize,
+    Serialize,
+    JsonSchema,
+    EnumIter,
+    Copy,
+    EnumCount,
 )]
 #[clap(rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
@@ -43,6 +63,7 @@ pub enum Step {
     Chezmoi,
     Chocolatey,
     Choosenim,
+    Chroot,
     CinnamonSpices,
     ClamAvDb,
     ClaudeCode,
@@ -52,6 +73,7 @@ pub enum Step {
     Composer,
     Conda,
     ConfigUpdate,
+    ContainerExec,
     Containers,
     Cursor,
     CursorAgent,
@@ -85,6 +107,7 @@ pub enum Step {
     HermesAgent,
     HomeManager,
     Hyprpm,
+    Incus,
     InstallRelease,
     // These names are miscapitalized on purpose, so the CLI name is
     //  `jetbrains_pycharm` instead of `jet_brains_py_charm`.
@@ -202,6 +225,13 @@ pub enum Step {
 }
 
 impl Step {
+    /// Name of the step on the command line, e.g. `pip3` for `Step::Pip3`.
+    pub fn cli_name(&self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
     #[expect(clippy::too_many_lines)]
     pub fn run(&self, runner: &mut Runner, ctx: &ExecutionContext) -> Result<()> {
         use Step::*;
@@ -294,6 +324,15 @@ impl Step {
                 runner.execute(*self, "Chocolatey", || windows::run_chocolatey(ctx))?
             }
             Choosenim => runner.execute(*self, "choosenim", || generic::run_choosenim(ctx))?,
+            Chroot =>
+            {
+                #[cfg(target_os = "linux")]
+                for directory in runner.collect(*self, "chroot", || linux::chroot_directories(ctx))? {
+                    runner.execute(*self, format!("chroot ({})", directory.display()), || {
+                        linux::run_chroot_update(ctx, &directory)
+                    })?;
+                }
+            }
             CinnamonSpices =>
             {
                 #[cfg(target_os = "linux")]
@@ -313,7 +352,43 @@ impl Step {
                 #[cfg(target_os = "linux")]
                 runner.execute(*self, "config-update", || linux::run_config_update(ctx))?
             }
-            Containers => runner.execute(*self, "Containers", || containers::run_containers(ctx))?,
+            ContainerExec => {
+                #[cfg(target_os = "linux")]
+                {
+                    let runtimes = ctx.config().containers_runtimes();
+                    for runtime in &runtimes {
+                        // Name the runtime in the keys only when there are several
+                        let key = |name: &str| match runtimes.len() {
+                            1 => format!("Container exec ({name})"),
+                            _ => format!("Container exec ({}: {name})", runtime.name()),
+                        };
+                        let targets = runner.collect(*self, key(&runtime.runtime.to_string()), || {
+                            container_exec::exec_targets(ctx, runtime)
+                        })?;
+                        for target in &targets {
+                            runner.execute(*self, key(target), || {
+                                container_exec::run_container_exec(ctx, runtime, target)
+                            })?;
+                        }
+                    }
+                }
+            }
+            Containers => {
+                let runtimes = ctx.config().containers_runtimes();
+                for (i, runtime) in runtimes.iter().enumerate() {
+                    // Compose projects are updated with the first runtime
+                    let compose = i == 0;
+                    if runtimes.len() > 1 {
+                        runner.execute(*self, format!("Containers ({})", runtime.name()), || {
+                            containers::run_containers(ctx, runtime, compose)
+                        })?;
+                    } else {
+                        runner.execute(*self, "Containers", || {
+                            containers::run_containers(ctx, runtime, compose)
+                        })?;
+                    }
+                }
+            }
             Cursor => runner.execute(*self, "Cursor extensions", || {
                 generic::run_cursor_extensions_update(ctx)
             })?,
@@ -337,7 +412,19 @@ impl Step {
             Distrobox =>
             {
                 #[cfg(target_os = "linux")]
-                runner.execute(*self, "distrobox", || linux::run_distrobox_update(ctx))?
+                match ctx.config().distrobox_mode() {
+                    DistroboxMode::Upgrade => {
+                        runner.execute(*self, "distrobox", || linux::run_distrobox_update(ctx))?
+                    }
+                    DistroboxMode::Topgrade => {
+                        let boxes = runner.collect(*self, "distrobox", || linux::distrobox_boxes(ctx))?;
+                        for name in &boxes {
+                            runner.execute(*self, format!("distrobox ({name})"), || {
+                                linux::run_distrobox_topgrade(ctx, name)
+                            })?;
+                        }
+                    }
+                }
             }
             DkpPacman =>
             {
@@ -369,7 +456,15 @@ impl Step {
             Gem => runner.execute(*self, "gem", || generic::run_gem(ctx))?,
             Getnf => runner.execute(*self, "getnf", || generic::run_getnf_update(ctx))?,
             Ghcup => runner.execute(*self, "ghcup", || generic::run_ghcup_update(ctx))?,
-            GitRepos => runner.execute(*self, "Git Repositories", || git::run_git_pull_or_fetch(ctx))?,
+            GitRepos => {
+                let statuses = RefCell::new(Vec::new());
+                runner.execute(*self, "Git Repositories", || {
+                    git::run_git_pull_or_fetch(ctx, &mut statuses.borrow_mut())
+                })?;
+                if let Ok(details) = serde_json::to_value(statuses.into_inner()) {
+                    runner.push_details("Git Repositories", details);
+                }
+            }
             GithubCliExtensions => runner.execute(*self, "GitHub CLI Extensions", || {
                 generic::run_ghcli_extensions_upgrade(ctx)
             })?,
@@ -402,6 +497,17 @@ impl Step {
                 #[cfg(unix)]
                 runner.execute(*self, "hyprpm", || unix::run_hyprpm(ctx))?
             }
+            Incus => {
+                #[cfg(target_os = "linux")]
+                {
+                    let instances = runner.collect(*self, "Incus", || incus::instances(ctx))?;
+                    for instance in &instances {
+                        runner.execute(*self, format!("Incus ({})", instance.name), || {
+                            incus::update_instance(ctx, instance)
+                        })?;
+                    }
+                }
+            }
             InstallRelease =>
             {
                 #[cfg(unix)]
@@ -577,14 +683,39 @@ impl Step {
                 runner.execute(*self, "rcm", || unix::run_rcm(ctx))?
             }
             Remotes => {
-                if let Some(topgrades) = ctx.config().remote_topgrades() {
-                    for remote_topgrade in topgrades
-                        .iter()
-                        .filter(|t| ctx.config().should_execute_remote(hostname(), t))
-                    {
-                        runner.execute(*self, format!("Remote ({remote_topgrade})"), || {
-                            crate::ssh::ssh_step(ctx, remote_topgrade)
-                        })?;
+                if ctx.config().should_run(*self) {
+                    let remotes: Vec<_> = runner
+                        .collect(*self, "Remotes", || ctx.config().remotes())?
+                        .into_iter()
+                        .filter(|remote| ctx.config().should_execute_remote(hostname(), &remote.destination))
+                        .collect();
+
+                    let jobs = ctx.config().remote_parallel();
+                    if jobs > 1 && !remotes.is_empty() {
+                        let runs =
+                            runner.collect(*self, "Remotes", || crate::ssh::ssh_parallel(ctx, &remotes, jobs))?;
+                        for (remote, run) in remotes.iter().zip(runs) {
+                            let key = format!("Remote ({})", remote.name);
+                            let result = RefCell::new(Some(run.result));
+                            let retried = Cell::new(false);
+                            runner.execute(*self, key.clone(), || match result.borrow_mut().take() {
+                                Some(result) => result,
+                                // Retrying a failed host runs it again interactively
+                                None => {
+                                    retried.set(true);
+                                    crate::ssh::ssh_step(ctx, remote)
+                                }
+                            })?;
+                            if let Some(report) = run.report.filter(|_| !retried.get()) {
+                                runner.push_nested_report(key, report);
+                            }
+                        }
+                    } else {
+                        for remote in &remotes {
+                            runner.execute(*self, format!("Remote ({})", remote.name), || {
+                                crate::ssh::ssh_step(ctx, remote)
+                            })?;
+                        }
                     }
                 }
             }
@@ -697,10 +828,14 @@ impl Step {
                 #[cfg(unix)]
                 runner.execute(*self, "tmux", || tmux::run_tpm(ctx))?
             }
-            Toolbx =>
-            {
+            Toolbx => {
                 #[cfg(target_os = "linux")]
-                runner.execute(*self, "toolbx", || toolbx::run_toolbx(ctx))?
+                {
+                    let toolboxes = runner.collect(*self, "toolbx", || toolbx::toolboxes(ctx))?;
+                    for tb in &toolboxes {
+                        runner.execute(*self, format!("toolbx ({tb})"), || toolbx::run_toolbx(ctx, tb))?;
+                    }
+                }
             }
             Tpack =>
             {
@@ -822,6 +957,7 @@ pub(crate) fn default_steps() -> Vec<Step> {
         AppMan,
         DebGet,
         Toolbx,
+        ContainerExec,
         Snap,
         Soar,
         Pacstall,
@@ -829,6 +965,8 @@ pub(crate) fn default_steps() -> Vec<Step> {
         Protonup,
         Protonplus,
         Distrobox,
+        Incus,
+        Chroot,
         DkpPacman,
         Firmware,
         Flatpak,
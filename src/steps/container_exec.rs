@@ -0,0 +1,148 @@
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+use color_eyre::eyre::Result;
+use itertools::Itertools;
+use rust_i18n::t;
+use tracing::debug;
+use wildmatch::WildMatch;
+
+use crate::command::CommandExt;
+use crate::config::ContainerRuntimeConfig;
+use crate::execution_context::ExecutionContext;
+use crate::executor::Executor;
+use crate::step::Step;
+use crate::steps::containers::execute;
+use crate::steps::remote::guest::{self, Guest};
+use crate::sudo::Sudo;
+use crate::terminal::print_separator;
+use crate::utils::require;
+
+/// Running containers of `runtime` to run Topgrade in, matching `exec_targets` or `exec_labels`.
+pub fn exec_targets(ctx: &ExecutionContext, runtime: &ContainerRuntimeConfig) -> Result<Vec<String>> {
+    let patterns = ctx.config().containers_exec_targets();
+    let labels = ctx.config().containers_exec_labels();
+    if patterns.is_empty() && labels.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let crt = require(runtime.runtime.to_string())?;
+    let sudo = if runtime.use_sudo {
+        Some(ctx.require_sudo()?)
+    } else {
+        None
+    };
+
+    let running = if patterns.is_empty() {
+        String::new()
+    } else {
+        execute(ctx, &crt, sudo)?
+            .always()
+            .args(["ps", "--format", "{{.Names}}"])
+            .output_checked_utf8()?
+            .stdout
+    };
+    let mut labelled = String::new();
+    for label in labels {
+        let output = execute(ctx, &crt, sudo)?
+            .always()
+            .args(["ps", "--filter", &format!("label={label}"), "--format", "{{.Names}}"])
+            .output_checked_utf8()?;
+        labelled.push_str(&output.stdout);
+    }
+
+    let targets = select_targets(&running, patterns, &labelled);
+    debug!("Containers to run Topgrade in: {:?}", targets);
+    Ok(targets)
+}
+
+/// The `running` containers matching one of the `patterns`, followed by the `labelled` ones,
+/// each once. Both are lists of names, one per line.
+fn select_targets(running: &str, patterns: &[String], labelled: &str) -> Vec<String> {
+    let patterns: Vec<_> = patterns.iter().map(|pattern| WildMatch::new(pattern)).collect();
+    running
+        .lines()
+        .filter(|name| patterns.iter().any(|pattern| pattern.matches(name)))
+        .chain(labelled.lines())
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unique()
+        .map(String::from)
+        .collect()
+}
+
+/// A running container of a runtime.
+struct Container<'a> {
+    crt: PathBuf,
+    sudo: Option<&'a Sudo>,
+    name: &'a str,
+}
+
+impl Guest for Container<'_> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn copy(&self, ctx: &ExecutionContext, source: &str, target: &str) -> Result<()> {
+        execute(ctx, &self.crt, self.sudo)?
+            .args(["cp", source, &format!("{}:{target}", self.name)])
+            .status_checked()
+    }
+
+    fn exec(&self, ctx: &ExecutionContext, env: &[String], interactive: bool) -> Result<Executor> {
+        let mut exec = execute(ctx, &self.crt, self.sudo)?;
+        exec.arg("exec");
+        if interactive {
+            exec.arg("-i");
+            if std::io::stdin().is_terminal() {
+                exec.arg("-t");
+            }
+        }
+        for variable in env {
+            exec.args(["--env", variable]);
+        }
+        exec.arg(self.name);
+        Ok(exec)
+    }
+}
+
+/// Copy the running Topgrade executable in `container` and run it there with the `exec_only` steps.
+pub fn run_container_exec(ctx: &ExecutionContext, runtime: &ContainerRuntimeConfig, container: &str) -> Result<()> {
+    let crt = require(runtime.runtime.to_string())?;
+    print_separator(t!("Container {name}", name = container));
+
+    let sudo = if runtime.use_sudo {
+        Some(ctx.require_sudo()?)
+    } else {
+        None
+    };
+
+    guest::run_topgrade(
+        ctx,
+        &Container {
+            crt,
+            sudo,
+            name: container,
+        },
+        ctx.config().containers_exec_only(),
+        ctx.config().yes(Step::ContainerExec),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_targets() {
+        let running = "web-1\nweb-2\ndb\n";
+        let patterns = ["web-*".to_string()];
+        assert_eq!(select_targets(running, &patterns, ""), ["web-1", "web-2"]);
+        assert_eq!(
+            select_targets(running, &patterns, "db\nweb-2\n"),
+            ["web-1", "web-2", "db"]
+        );
+        assert_eq!(select_targets("", &[], "db\n\n"), ["db"]);
+        assert!(select_targets(running, &["cache".to_string()], "").is_empty());
+    }
+}
@@ -1,18 +1,28 @@
+use std::collections::HashMap;
+use std::env;
 use std::fmt::{Display, Formatter};
 use std::io;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use color_eyre::eyre::Context;
 use color_eyre::eyre::Result;
-use color_eyre::eyre::{OptionExt, bail};
+use color_eyre::eyre::{OptionExt, bail, eyre};
+use glob::glob;
 use tracing::{debug, error, warn};
 use wildmatch::WildMatch;
 
+use crate::HOME_DIR;
 use crate::command::CommandExt;
+use crate::config::{ContainerRuntime, ContainerRuntimeConfig, ContainersMode};
 use crate::error::{SkipStep, TopgradeError};
+use crate::executor::Executor;
+use crate::sudo::Sudo;
 use crate::terminal::print_separator;
-use crate::{execution_context::ExecutionContext, utils::require};
+use crate::{
+    execution_context::ExecutionContext,
+    utils::{require, which},
+};
 use rust_i18n::t;
 
 // A string found in the output of docker for containers that weren't found in
@@ -36,15 +46,190 @@ struct Container {
     ///
     /// format: `OS/Architecture`, e.g., `linux/amd64`.
     platform: String,
+    /// Digests of the image in registries
+    ///
+    /// format: `sha256:...`
+    digests: Vec<String>,
+    /// ID of the image, the digest of its configuration
+    ///
+    /// format: `sha256:...`
+    id: String,
 }
 
 impl Container {
     /// Construct a new `Container`.
-    fn new(repo_tag: String, platform: String) -> Self {
-        Self { repo_tag, platform }
+    fn new(repo_tag: String, platform: String, digests: Vec<String>, id: String) -> Self {
+        Self {
+            repo_tag,
+            platform,
+            digests,
+            id,
+        }
+    }
+
+    /// Whether `digest`, of a manifest or of the configuration of an image, is the one of
+    /// the local image.
+    fn is_current(&self, digest: &str) -> bool {
+        self.digests.iter().any(|local| local == digest) || self.id == digest
+    }
+}
+
+/// A way to get the digest of an image in its registry without pulling it.
+enum DigestSource {
+    /// `podman manifest inspect`, giving the digest of the image for the platform of a
+    /// multi-platform image, which Podman records along with the digest of the whole image,
+    /// or the digest of the configuration of a single-platform image
+    Manifest,
+    /// `docker buildx imagetools inspect`, using the Docker credentials
+    Buildx,
+    Skopeo {
+        skopeo: PathBuf,
+        /// The credentials of the runtime, when not the default ones of skopeo
+        auth_file: Option<PathBuf>,
+    },
+}
+
+impl DigestSource {
+    /// Find the ways to query registries, preferring the tools of `runtime` which use its credentials.
+    ///
+    /// `docker manifest inspect` is not used: it does not give the digest of multi-platform images
+    /// that Docker records.
+    fn detect(ctx: &ExecutionContext, runtime: ContainerRuntime, crt: &Path, sudo: Option<&Sudo>) -> Vec<Self> {
+        let mut sources = Vec::new();
+        match runtime {
+            ContainerRuntime::Podman => sources.push(Self::Manifest),
+            ContainerRuntime::Docker => {
+                let buildx = execute(ctx, crt, sudo).is_ok_and(|buildx| {
+                    buildx
+                        .always()
+                        .args(["buildx", "version"])
+                        .output_checked_utf8()
+                        .is_ok()
+                });
+                if buildx {
+                    sources.push(Self::Buildx);
+                }
+            }
+        }
+        if let Some(skopeo) = which("skopeo") {
+            sources.push(Self::Skopeo {
+                skopeo,
+                auth_file: auth_file(runtime, sudo.is_some()),
+            });
+        }
+
+        sources
+    }
+
+    /// The digests of `container` in its registry, trying each of the `sources`.
+    fn digest(
+        sources: &[Self],
+        ctx: &ExecutionContext,
+        crt: &Path,
+        sudo: Option<&Sudo>,
+        container: &Container,
+    ) -> Result<String> {
+        let mut error = None;
+        for source in sources {
+            match source.digest_with(ctx, crt, sudo, container) {
+                Ok(digest) => return Ok(digest),
+                Err(e) => {
+                    debug!("Failed to get the digest of container '{}': {:?}", container, e);
+                    error = Some(e);
+                }
+            }
+        }
+
+        Err(error.unwrap_or_else(|| eyre!("No way to get the digest of {container}")))
+    }
+
+    fn digest_with(
+        &self,
+        ctx: &ExecutionContext,
+        crt: &Path,
+        sudo: Option<&Sudo>,
+        container: &Container,
+    ) -> Result<String> {
+        let digest = match self {
+            Self::Manifest => {
+                let output = execute(ctx, crt, sudo)?
+                    .always()
+                    .args(["manifest", "inspect"])
+                    .arg(format!("docker://{}", container.repo_tag))
+                    .output_checked_utf8()?;
+                manifest_digest(&output.stdout, &container.platform)
+                    .or_else(|| config_digest(&output.stdout))
+                    .ok_or_else(|| eyre!("No manifest for {container}"))?
+            }
+            Self::Buildx => {
+                let output = execute(ctx, crt, sudo)?
+                    .always()
+                    .args(["buildx", "imagetools", "inspect", "--format", "{{json .Manifest}}"])
+                    .arg(&container.repo_tag)
+                    .output_checked_utf8()?;
+                serde_json::from_str::<serde_json::Value>(&output.stdout)?["digest"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string()
+            }
+            Self::Skopeo { skopeo, auth_file } => {
+                // Like the runtime, with its privileges
+                let mut command = execute(ctx, skopeo, sudo)?.always();
+                command.args(["inspect", "--no-tags", "--format", "{{.Digest}}"]);
+                if let Some(auth_file) = auth_file {
+                    command.arg("--authfile").arg(auth_file);
+                }
+                command
+                    .arg(format!("docker://{}", container.repo_tag))
+                    .output_checked_utf8()?
+                    .stdout
+                    .trim()
+                    .to_string()
+            }
+        };
+
+        if !digest.starts_with("sha256:") {
+            bail!("Unexpected digest '{digest}' for {container}");
+        }
+        Ok(digest)
     }
 }
 
+/// The credentials file of `runtime`, for skopeo, when it is not one skopeo reads by default:
+/// `REGISTRY_AUTH_FILE` of Podman is not kept by sudo, and the Docker `config.json` is only read
+/// from the home directory of the user running skopeo.
+fn auth_file(runtime: ContainerRuntime, sudo: bool) -> Option<PathBuf> {
+    match runtime {
+        ContainerRuntime::Podman => env::var_os("REGISTRY_AUTH_FILE").map(PathBuf::from),
+        ContainerRuntime::Docker => env::var_os("DOCKER_CONFIG")
+            .map(|config| PathBuf::from(config).join("config.json"))
+            .or_else(|| (!sudo).then(|| HOME_DIR.join(".docker/config.json")))
+            .filter(|path| path.exists()),
+    }
+}
+
+/// The digest of the image for `platform` (`os/arch`) in the output of `manifest inspect`
+/// for a multi-platform image.
+fn manifest_digest(output: &str, platform: &str) -> Option<String> {
+    let (os, architecture) = platform.split_once('/')?;
+    let manifest: serde_json::Value = serde_json::from_str(output).ok()?;
+    manifest["manifests"]
+        .as_array()?
+        .iter()
+        .find(|entry| entry["platform"]["os"] == os && entry["platform"]["architecture"] == architecture)?["digest"]
+        .as_str()
+        .map(String::from)
+}
+
+/// The digest of the configuration in the output of `manifest inspect` for a single-platform
+/// image, which is the ID of the image once pulled.
+///
+/// Podman reformats a single manifest, so its own digest cannot be computed from the output.
+fn config_digest(output: &str) -> Option<String> {
+    let manifest: serde_json::Value = serde_json::from_str(output).ok()?;
+    manifest["config"]["digest"].as_str().map(String::from)
+}
+
 impl Display for Container {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         // e.g., "`fedora:latest` for `linux/amd64`"
@@ -60,24 +245,127 @@ impl Display for Container {
     }
 }
 
+/// Format of `image inspect`, parsed by `parse_image_inspect()`.
+const IMAGE_INSPECT_FORMAT: &str = "{{.Id}} {{.Os}}/{{.Architecture}} {{json .RepoDigests}} {{json .Config.Labels}}";
+
+/// An image, from the output of `image inspect --format IMAGE_INSPECT_FORMAT`.
+struct ImageInspect {
+    /// format: `sha256:...`, whether the runtime gives the algorithm or not
+    id: String,
+    platform: String,
+    digests: Vec<String>,
+    labels: HashMap<String, String>,
+}
+
+/// Parse the output of `image inspect --format IMAGE_INSPECT_FORMAT`.
+fn parse_image_inspect(output: &str) -> Option<ImageInspect> {
+    let (id, rest) = output.trim().split_once(' ')?;
+    let (platform, json) = rest.split_once(' ')?;
+    if !platform.contains('/') {
+        return None;
+    }
+
+    let mut values = serde_json::Deserializer::from_str(json).into_iter::<serde_json::Value>();
+    let mut next = || values.next().and_then(Result::ok).unwrap_or_default();
+    let digests = repo_digests(serde_json::from_value(next()).unwrap_or_default());
+    let labels = serde_json::from_value::<Option<HashMap<String, String>>>(next())
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+
+    Some(ImageInspect {
+        id: format!("sha256:{}", id.trim_start_matches("sha256:")),
+        platform: platform.to_string(),
+        digests,
+        labels,
+    })
+}
+
+/// Format of `container inspect`, parsed by `parse_container_inspect()`.
+const CONTAINER_INSPECT_FORMAT: &str = "{{.Image}} {{json .Config.Labels}}";
+
+/// Parse the output of `container inspect --format CONTAINER_INSPECT_FORMAT`, a line per
+/// container, into the image ID and the labels of each container.
+fn parse_container_inspect(output: &str) -> Vec<(String, HashMap<String, String>)> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .map(|(image, labels)| {
+            let labels = serde_json::from_str::<Option<HashMap<String, String>>>(labels)
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            (image.trim_start_matches("sha256:").to_string(), labels)
+        })
+        .collect()
+}
+
+/// The image ID and the labels of each container, running or not.
+fn container_labels(
+    ctx: &ExecutionContext,
+    crt: &Path,
+    sudo: Option<&Sudo>,
+) -> Result<Vec<(String, HashMap<String, String>)>> {
+    let ids = execute(ctx, crt, sudo)?
+        .always()
+        .args(["ps", "--all", "--quiet"])
+        .output_checked_utf8()?;
+    let ids: Vec<&str> = ids.stdout.split_whitespace().collect();
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let output = execute(ctx, crt, sudo)?
+        .always()
+        .args(["container", "inspect", "--format", CONTAINER_INSPECT_FORMAT])
+        .args(ids)
+        .output_checked_utf8()?;
+    Ok(parse_container_inspect(&output.stdout))
+}
+
+/// The labels of the image `image_id` (possibly abbreviated), followed by the ones of its
+/// `containers`.
+fn image_and_container_labels<'a>(
+    image_id: &str,
+    image_labels: &'a HashMap<String, String>,
+    containers: &'a [(String, HashMap<String, String>)],
+) -> Vec<&'a HashMap<String, String>> {
+    let image_id = image_id.trim_start_matches("sha256:");
+    std::iter::once(image_labels)
+        .chain(
+            containers
+                .iter()
+                .filter(|(image, _)| !image_id.is_empty() && image.starts_with(image_id))
+                .map(|(_, labels)| labels),
+        )
+        .collect()
+}
+
+/// Whether any of the `selectors` (`key` or `key=value`) matches the `labels`.
+fn labels_match(selectors: &[String], labels: &HashMap<String, String>) -> bool {
+    selectors.iter().any(|selector| match selector.split_once('=') {
+        Some((key, value)) => labels.get(key).is_some_and(|v| v == value),
+        None => labels.contains_key(selector),
+    })
+}
+
 /// Returns a Vector of all containers, with Strings in the format
 /// "REGISTRY/[PATH/]CONTAINER_NAME:TAG"
 ///
 /// Containers specified in `ignored_containers` will be filtered out.
-fn list_containers(ctx: &ExecutionContext, crt: &Path) -> Result<Vec<Container>> {
-    let ignored_containers = ctx.config().containers_ignored_tags().map(|patterns| {
-        patterns
-            .iter()
-            .map(|pattern| WildMatch::new(pattern))
-            .collect::<Vec<WildMatch>>()
-    });
+fn list_containers(ctx: &ExecutionContext, crt: &Path, runtime: &ContainerRuntimeConfig) -> Result<Vec<Container>> {
+    let ignored_containers = runtime
+        .ignored_containers
+        .iter()
+        .map(|pattern| WildMatch::new(pattern))
+        .collect::<Vec<WildMatch>>();
 
     debug!(
         "Querying '{} image ls --format \"{{{{.Repository}}}}:{{{{.Tag}}}}/{{{{.ID}}}}\"' for containers",
         crt.display()
     );
 
-    let sudo = if ctx.config().containers_use_sudo() {
+    let sudo = if runtime.use_sudo {
         Some(ctx.require_sudo()?)
     } else {
         None
@@ -95,6 +383,13 @@ fn list_containers(ctx: &ExecutionContext, crt: &Path) -> Result<Vec<Container>>
             .output_checked_utf8()?
     };
 
+    // Images are also selected by the labels of their containers
+    let containers = if runtime.include_labels.is_empty() && runtime.exclude_labels.is_empty() {
+        Vec::new()
+    } else {
+        container_labels(ctx, crt, sudo)?
+    };
+
     let mut retval = vec![];
     for line in output.stdout.lines() {
         if line.starts_with("localhost") {
@@ -126,55 +421,80 @@ fn list_containers(ctx: &ExecutionContext, crt: &Path) -> Result<Vec<Container>>
         }
         let (repo_tag, image_id) = (split_res[0], split_res[1]);
 
-        if let Some(ref ignored_containers) = ignored_containers
-            && ignored_containers.iter().any(|pattern| pattern.matches(repo_tag))
-        {
+        if ignored_containers.iter().any(|pattern| pattern.matches(repo_tag)) {
             debug!("Skipping ignored container '{}'", line);
             continue;
         }
 
         debug!(
-            "Querying '{} image inspect --format \"{{{{.Os}}}}/{{{{.Architecture}}}}\"' for container {}",
+            "Querying '{} image inspect --format \"{}\"' for container {}",
             crt.display(),
+            IMAGE_INSPECT_FORMAT,
             image_id
         );
         let inspect_output = if let Some(sudo) = sudo {
             sudo.execute(ctx, crt)?
                 .always()
-                .args(["image", "inspect", image_id, "--format", "{{.Os}}/{{.Architecture}}"])
+                .args(["image", "inspect", image_id, "--format", IMAGE_INSPECT_FORMAT])
                 .output_checked_utf8()?
         } else {
             ctx.execute(crt)
                 .always()
-                .args(["image", "inspect", image_id, "--format", "{{.Os}}/{{.Architecture}}"])
+                .args(["image", "inspect", image_id, "--format", IMAGE_INSPECT_FORMAT])
                 .output_checked_utf8()?
         };
-        let mut platform = inspect_output.stdout;
-        // truncate the tailing new line character
-        platform.truncate(platform.len() - 1);
-        if !platform.contains('/') {
+        let Some(image) = parse_image_inspect(&inspect_output.stdout) else {
             bail!(format!(
-                "Got erroneous output from `{} image ls --format \"{{.Repository}}:{{.Tag}} {{.ID}}\"; Expected platform to contain '/'",
-                crt.display()
+                "Got erroneous output from `{} image inspect --format \"{}\"`; Expected the ID and the platform, containing '/'",
+                crt.display(),
+                IMAGE_INSPECT_FORMAT
             ));
+        };
+
+        let labels = image_and_container_labels(image_id, &image.labels, &containers);
+        if !runtime.include_labels.is_empty()
+            && !labels
+                .iter()
+                .any(|labels| labels_match(&runtime.include_labels, labels))
+        {
+            debug!("Skipping container '{}' without included labels", line);
+            continue;
+        }
+        if labels
+            .iter()
+            .any(|labels| labels_match(&runtime.exclude_labels, labels))
+        {
+            debug!("Skipping container '{}' with excluded labels", line);
+            continue;
         }
 
-        retval.push(Container::new(repo_tag.to_string(), platform));
+        retval.push(Container::new(
+            repo_tag.to_string(),
+            image.platform,
+            image.digests,
+            image.id,
+        ));
     }
 
     Ok(retval)
 }
 
-pub fn run_containers(ctx: &ExecutionContext) -> Result<()> {
-    // Check what runtime is specified in the config
-    let container_runtime = ctx.config().containers_runtime().to_string();
-    let use_sudo = ctx.config().containers_use_sudo();
-    let crt = require(container_runtime)?;
+/// Update the containers of `runtime`, and the compose projects if `compose` is set.
+pub fn run_containers(ctx: &ExecutionContext, runtime: &ContainerRuntimeConfig, compose: bool) -> Result<()> {
+    let crt = require(runtime.runtime.to_string())?;
     debug!("Using container runtime '{}'", crt.display());
 
-    print_separator(t!("Containers"));
+    if ctx.config().containers_runtimes().len() > 1 {
+        print_separator(format!("{} ({})", t!("Containers"), runtime.name()));
+    } else {
+        print_separator(t!("Containers"));
+    }
 
-    let sudo = if use_sudo { Some(ctx.require_sudo()?) } else { None };
+    let sudo = if runtime.use_sudo {
+        Some(ctx.require_sudo()?)
+    } else {
+        None
+    };
 
     let output = if let Some(sudo) = sudo {
         sudo.execute(ctx, &crt)?
@@ -221,10 +541,78 @@ pub fn run_containers(ctx: &ExecutionContext) -> Result<()> {
         );
     }
 
-    let containers = list_containers(ctx, &crt).context("Failed to list Docker containers")?;
+    if runtime.mode == ContainersMode::AutoUpdate {
+        if !matches!(runtime.runtime, ContainerRuntime::Podman) {
+            bail!("The `auto_update` containers mode requires podman");
+        }
+        podman_auto_update(ctx, &crt, sudo)?;
+    } else {
+        pull_containers(ctx, &crt, sudo, runtime)?;
+    }
+
+    if compose && let Some(projects) = ctx.config().containers_compose_projects() {
+        update_compose_projects(ctx, &crt, sudo, projects)?;
+    }
+
+    if runtime.system_prune {
+        // Run system prune to clean up unused containers, networks, and build cache
+        if let Some(sudo) = sudo {
+            sudo.execute(ctx, &crt)?
+                .args(["system", "prune", "--force"])
+                .status_checked()?
+        } else {
+            ctx.execute(&crt)
+                .args(["system", "prune", "--force"])
+                .status_checked()?
+        }
+    // Only run `image prune` if we don't run `system prune`
+    } else if ctx.config().cleanup() {
+        // Remove dangling images
+        debug!("Removing dangling images");
+        if let Some(sudo) = sudo {
+            sudo.execute(ctx, &crt)?
+                .args(["image", "prune", "-f"])
+                .status_checked()?
+        } else {
+            ctx.execute(&crt).args(["image", "prune", "-f"]).status_checked()?
+        }
+    }
+
+    Ok(())
+}
+
+/// Pull the images of the containers of `runtime` whose digest changed in their registry.
+fn pull_containers(
+    ctx: &ExecutionContext,
+    crt: &Path,
+    sudo: Option<&Sudo>,
+    runtime: &ContainerRuntimeConfig,
+) -> Result<()> {
+    let containers = list_containers(ctx, crt, runtime).context("Failed to list Docker containers")?;
     debug!("Containers to inspect: {:?}", containers);
 
+    // Querying the registries is not dry, all the images are pulled with `--dry-run`
+    let digest_sources = if ctx.run_type().dry() {
+        Vec::new()
+    } else {
+        DigestSource::detect(ctx, runtime.runtime, crt, sudo)
+    };
+    let mut up_to_date = 0;
+    let mut moved = Vec::new();
+
     for container in &containers {
+        if !digest_sources.is_empty() {
+            match DigestSource::digest(&digest_sources, ctx, crt, sudo, container) {
+                Ok(digest) if container.is_current(&digest) => {
+                    debug!("Container '{}' is up to date ({})", container, digest);
+                    up_to_date += 1;
+                    continue;
+                }
+                Ok(digest) => debug!("Container '{}' changed to {}", container, digest),
+                Err(e) => debug!("Failed to get the digest of container '{}': {:?}", container, e),
+            }
+        }
+
         debug!("Pulling container '{}'", container);
         let mut args = vec!["pull", container.repo_tag.as_str()];
         if container.platform.as_str() != "/" {
@@ -233,9 +621,9 @@ pub fn run_containers(ctx: &ExecutionContext) -> Result<()> {
         }
 
         let mut exec = if let Some(sudo) = sudo {
-            sudo.execute(ctx, &crt)?
+            sudo.execute(ctx, crt)?
         } else {
-            ctx.execute(&crt)
+            ctx.execute(crt)
         };
 
         if let Err(e) = exec.args(&args).status_checked() {
@@ -260,31 +648,424 @@ pub fn run_containers(ctx: &ExecutionContext) -> Result<()> {
 
             return Err(e);
         }
-    }
 
-    if ctx.config().containers_system_prune() {
-        // Run system prune to clean up unused containers, networks, and build cache
-        if let Some(sudo) = sudo {
-            sudo.execute(ctx, &crt)?
-                .args(["system", "prune", "--force"])
-                .status_checked()?
-        } else {
-            ctx.execute(&crt)
-                .args(["system", "prune", "--force"])
-                .status_checked()?
+        if ctx.run_type().dry() {
+            continue;
         }
-    // Only run `image prune` if we don't run `system prune`
-    } else if ctx.config().cleanup() {
-        // Remove dangling images
-        debug!("Removing dangling images");
-        if let Some(sudo) = sudo {
-            sudo.execute(ctx, &crt)?
-                .args(["image", "prune", "-f"])
-                .status_checked()?
+
+        let digests = image_digests(ctx, crt, sudo, &container.repo_tag)?;
+        if digests.iter().any(|digest| container.digests.contains(digest)) {
+            up_to_date += 1;
         } else {
-            ctx.execute(&crt).args(["image", "prune", "-f"]).status_checked()?
+            moved.push((container, digests));
+        }
+    }
+
+    if !ctx.run_type().dry() && !containers.is_empty() {
+        println!(
+            "{}",
+            t!(
+                "{up_to_date} images up to date, {updated} updated",
+                up_to_date = up_to_date,
+                updated = moved.len()
+            )
+        );
+        for (container, digests) in &moved {
+            println!(
+                "  {}: {} -> {}",
+                container.repo_tag,
+                short_digest(container.digests.first()),
+                short_digest(digests.first())
+            );
         }
     }
 
     Ok(())
 }
+
+/// A line of the output of `podman auto-update`.
+#[derive(Debug, PartialEq, Eq)]
+struct AutoUpdate {
+    unit: String,
+    container: String,
+    image: String,
+    /// `true`, `false`, `pending` (with `--dry-run`), `rolled back` or `failed`
+    updated: String,
+}
+
+impl AutoUpdate {
+    /// Format of `podman auto-update`, parsed by `parse()`.
+    const FORMAT: &str = "{{.Unit}}\t{{.Container}}\t{{.Image}}\t{{.Updated}}";
+
+    fn parse(output: &str) -> Vec<Self> {
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t').map(str::trim);
+                Some(Self {
+                    unit: fields.next()?.to_string(),
+                    container: fields.next()?.to_string(),
+                    image: fields.next()?.to_string(),
+                    updated: fields.next()?.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    fn failed(&self) -> bool {
+        matches!(self.updated.as_str(), "failed" | "rolled back")
+    }
+}
+
+/// Run `podman auto-update`, which updates the containers with an `io.containers.autoupdate`
+/// label and restarts their systemd units, and report the result of each unit.
+fn podman_auto_update(ctx: &ExecutionContext, crt: &Path, sudo: Option<&Sudo>) -> Result<()> {
+    // With `--dry-run`, podman only checks for updates
+    let output = execute(ctx, crt, sudo)?
+        .always()
+        .args(["auto-update", "--format", AutoUpdate::FORMAT])
+        .arg_if(ctx.run_type().dry(), "--dry-run")
+        .output_checked_utf8()?;
+
+    let updates = AutoUpdate::parse(&output.stdout);
+    if updates.is_empty() {
+        println!("{}", t!("No containers are set up for podman auto-update"));
+    }
+    for update in &updates {
+        println!(
+            "{} ({}, {}): {}",
+            update.unit, update.container, update.image, update.updated
+        );
+    }
+
+    let failed = updates.iter().filter(|update| update.failed()).count();
+    if failed > 0 {
+        bail!("podman auto-update failed for {failed} units");
+    }
+
+    Ok(())
+}
+
+/// Create an `Executor` for the container runtime, with `sudo` if any.
+pub(crate) fn execute(ctx: &ExecutionContext, crt: &Path, sudo: Option<&Sudo>) -> Result<Executor> {
+    match sudo {
+        Some(sudo) => sudo.execute(ctx, crt),
+        None => Ok(ctx.execute(crt)),
+    }
+}
+
+/// Pull the images of the compose projects matching `patterns`, and recreate the containers
+/// whose image changed.
+fn update_compose_projects(ctx: &ExecutionContext, crt: &Path, sudo: Option<&Sudo>, patterns: &[String]) -> Result<()> {
+    let mut failed = false;
+
+    for pattern in patterns {
+        let pattern = shellexpand::tilde(pattern);
+        let files = match glob(&pattern) {
+            Ok(files) => files,
+            Err(e) => {
+                error!("Invalid compose project pattern '{pattern}': {e}");
+                failed = true;
+                continue;
+            }
+        };
+
+        for file in files {
+            let file = match file {
+                Ok(file) => file,
+                Err(e) => {
+                    error!("Error in path {e}");
+                    continue;
+                }
+            };
+
+            if let Err(e) = update_compose_project(ctx, crt, sudo, &file) {
+                error!("Updating compose project '{}' failed: {e:?}", file.display());
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        bail!("Failed to update some compose projects");
+    }
+
+    Ok(())
+}
+
+/// Pull the images of the compose project `file` and bring it up, reporting the recreated services.
+fn update_compose_project(ctx: &ExecutionContext, crt: &Path, sudo: Option<&Sudo>, file: &Path) -> Result<()> {
+    println!("{}", t!("Updating compose project {project}", project = file.display()));
+
+    let before = compose_containers(ctx, crt, sudo, file)?;
+
+    execute(ctx, crt, sudo)?
+        .arg("compose")
+        .arg("-f")
+        .arg(file)
+        .arg("pull")
+        .status_checked()?;
+    execute(ctx, crt, sudo)?
+        .arg("compose")
+        .arg("-f")
+        .arg(file)
+        .args(["up", "-d"])
+        .arg_if(ctx.config().containers_compose_remove_orphans(), "--remove-orphans")
+        .status_checked()?;
+
+    if ctx.run_type().dry() {
+        return Ok(());
+    }
+
+    let after = compose_containers(ctx, crt, sudo, file)?;
+    let recreated = recreated_services(&before, &after);
+    if recreated.is_empty() {
+        println!(
+            "{}",
+            t!("No services were recreated in {project}", project = file.display())
+        );
+    } else {
+        println!(
+            "{}",
+            t!(
+                "Recreated services in {project}: {services}",
+                project = file.display(),
+                services = recreated.join(", ")
+            )
+        );
+    }
+
+    Ok(())
+}
+
+/// The IDs of the containers of each service of the compose project `file`.
+fn compose_containers(
+    ctx: &ExecutionContext,
+    crt: &Path,
+    sudo: Option<&Sudo>,
+    file: &Path,
+) -> Result<Vec<(String, String)>> {
+    let services = execute(ctx, crt, sudo)?
+        .always()
+        .arg("compose")
+        .arg("-f")
+        .arg(file)
+        .args(["config", "--services"])
+        .output_checked_utf8()?
+        .stdout;
+
+    services
+        .lines()
+        .map(str::trim)
+        .filter(|service| !service.is_empty())
+        .map(|service| {
+            let ids = execute(ctx, crt, sudo)?
+                .always()
+                .arg("compose")
+                .arg("-f")
+                .arg(file)
+                .args(["ps", "-q", service])
+                .output_checked_utf8()?
+                .stdout;
+            Ok((service.to_string(), ids.trim().to_string()))
+        })
+        .collect()
+}
+
+/// The registry digests of the local image `repo_tag`.
+fn image_digests(ctx: &ExecutionContext, crt: &Path, sudo: Option<&Sudo>, repo_tag: &str) -> Result<Vec<String>> {
+    let output = execute(ctx, crt, sudo)?
+        .always()
+        .args(["image", "inspect", repo_tag, "--format", "{{json .RepoDigests}}"])
+        .output_checked_utf8()?;
+
+    Ok(parse_repo_digests(output.stdout.trim()))
+}
+
+/// Parse the `RepoDigests` of an image, e.g. `["docker.io/library/nginx@sha256:..."]`, into digests.
+fn parse_repo_digests(repo_digests: &str) -> Vec<String> {
+    self::repo_digests(serde_json::from_str(repo_digests).unwrap_or_default())
+}
+
+/// The digests in `RepoDigests`, e.g. `sha256:...` for `docker.io/library/nginx@sha256:...`.
+fn repo_digests(repo_digests: Option<Vec<String>>) -> Vec<String> {
+    repo_digests
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|repo_digest| repo_digest.split_once('@'))
+        .map(|(_, digest)| digest.to_string())
+        .collect()
+}
+
+/// Shorten a digest like the runtimes do, e.g. `sha256:0123456789ab`.
+fn short_digest(digest: Option<&String>) -> &str {
+    digest.map_or("none", |digest| &digest[..digest.len().min("sha256:".len() + 12)])
+}
+
+/// The services whose containers changed between `before` and `after`.
+fn recreated_services<'a>(before: &[(String, String)], after: &'a [(String, String)]) -> Vec<&'a str> {
+    after
+        .iter()
+        .filter(|(service, ids)| {
+            !ids.is_empty()
+                && before
+                    .iter()
+                    .find(|(s, _)| s == service)
+                    .is_none_or(|(_, previous)| previous != ids)
+        })
+        .map(|(service, _)| service.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn containers(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(service, ids)| (service.to_string(), ids.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_repo_digests() {
+        assert_eq!(
+            parse_repo_digests(r#"["nginx@sha256:aaa","docker.io/library/nginx@sha256:bbb"]"#),
+            ["sha256:aaa", "sha256:bbb"]
+        );
+        assert!(parse_repo_digests("[]").is_empty());
+        assert!(parse_repo_digests("null").is_empty());
+        assert!(parse_repo_digests("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_image_inspect() {
+        let image = parse_image_inspect(
+            "sha256:fff linux/amd64 [\"nginx@sha256:aaa\"] {\"maintainer\":\"NGINX Docker Maintainers\",\"pinned\":\"true\"}\n",
+        )
+        .unwrap();
+        assert_eq!(image.id, "sha256:fff");
+        assert_eq!(image.platform, "linux/amd64");
+        assert_eq!(image.digests, ["sha256:aaa"]);
+        assert_eq!(image.labels["maintainer"], "NGINX Docker Maintainers");
+
+        // Podman gives the ID without the algorithm
+        let image = parse_image_inspect("fff linux/arm64 null null").unwrap();
+        assert_eq!(image.id, "sha256:fff");
+        assert!(image.digests.is_empty());
+        assert!(image.labels.is_empty());
+
+        assert!(parse_image_inspect("fff linux").is_none());
+        assert!(parse_image_inspect("linux/amd64 null null").is_none());
+    }
+
+    #[test]
+    fn test_container_labels_select_images() {
+        let containers = parse_container_inspect(concat!(
+            "sha256:d80fea9c32b4aa0f {\"org.example.pinned\":\"true\"}\n",
+            "3f57d9401f8d33c2 null\n",
+        ));
+        assert_eq!(containers.len(), 2);
+
+        let image_labels = HashMap::new();
+        let labels = image_and_container_labels("d80fea9c32b4", &image_labels, &containers);
+        assert_eq!(labels.len(), 2);
+        assert!(
+            labels
+                .iter()
+                .any(|labels| labels_match(&["org.example.pinned".to_string()], labels))
+        );
+
+        let labels = image_and_container_labels("3f57d9401f8d", &image_labels, &containers);
+        assert!(
+            !labels
+                .iter()
+                .any(|labels| labels_match(&["org.example.pinned".to_string()], labels))
+        );
+
+        let labels = image_and_container_labels("0123456789ab", &image_labels, &containers);
+        assert_eq!(labels, [&image_labels]);
+    }
+
+    #[test]
+    fn test_manifest_digest() {
+        let output = r#"{
+            "schemaVersion": 2,
+            "manifests": [
+                {"digest": "sha256:aaa", "platform": {"architecture": "amd64", "os": "linux"}},
+                {"digest": "sha256:bbb", "platform": {"architecture": "arm64", "os": "linux", "variant": "v8"}}
+            ]
+        }"#;
+        assert_eq!(manifest_digest(output, "linux/arm64"), Some("sha256:bbb".to_string()));
+        assert_eq!(manifest_digest(output, "linux/s390x"), None);
+        assert_eq!(
+            manifest_digest(r#"{"schemaVersion": 2, "layers": []}"#, "linux/amd64"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_single_manifest_digest() {
+        let output = r#"{
+            "schemaVersion": 2,
+            "config": {"mediaType": "application/vnd.oci.image.config.v1+json", "digest": "sha256:ccc"},
+            "layers": [{"digest": "sha256:ddd"}]
+        }"#;
+        assert_eq!(manifest_digest(output, "linux/amd64"), None);
+        assert_eq!(config_digest(output), Some("sha256:ccc".to_string()));
+
+        let container = Container::new(
+            "docker.io/library/alpine:latest".to_string(),
+            "linux/amd64".to_string(),
+            vec!["sha256:aaa".to_string()],
+            "sha256:ccc".to_string(),
+        );
+        assert!(container.is_current("sha256:ccc"));
+        assert!(container.is_current("sha256:aaa"));
+        assert!(!container.is_current("sha256:ddd"));
+    }
+
+    #[test]
+    fn test_labels_match() {
+        let labels = HashMap::from([("pinned".to_string(), "true".to_string())]);
+        assert!(labels_match(&["pinned".to_string()], &labels));
+        assert!(labels_match(&["other".to_string(), "pinned=true".to_string()], &labels));
+        assert!(!labels_match(&["pinned=false".to_string()], &labels));
+        assert!(!labels_match(&[], &labels));
+    }
+
+    #[test]
+    fn test_parse_auto_update() {
+        let output = "container-web.service\t1a2b3c (web)\tdocker.io/library/nginx:latest\ttrue\n\
+                      container-db.service\t4d5e6f (db)\tdocker.io/library/postgres:16\trolled back\n";
+        let updates = AutoUpdate::parse(output);
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].unit, "container-web.service");
+        assert_eq!(updates[0].container, "1a2b3c (web)");
+        assert!(!updates[0].failed());
+        assert!(updates[1].failed());
+    }
+
+    #[test]
+    fn test_short_digest() {
+        let digest = "sha256:0123456789abcdef".to_string();
+        assert_eq!(short_digest(Some(&digest)), "sha256:0123456789ab");
+        assert_eq!(short_digest(None), "none");
+    }
+
+    #[test]
+    fn test_recreated_services() {
+        let before = containers(&[("web", "aaa"), ("db", "bbb"), ("worker", "")]);
+        let after = containers(&[
+            ("web", "ccc"),
+            ("db", "bbb"),
+            ("worker", "ddd"),
+            ("cache", "eee"),
+            ("cron", ""),
+        ]);
+
+        assert_eq!(recreated_services(&before, &after), ["web", "worker", "cache"]);
+        assert!(recreated_services(&before, &before).is_empty());
+    }
+}
@@ -1,23 +1,35 @@
-use std::collections::HashSet;
-use std::io;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::io::{IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Output, Stdio};
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fs, io};
 
 use color_eyre::eyre::Context;
 use color_eyre::eyre::{Result, eyre};
 use console::style;
+use crossterm::cursor::MoveToPreviousLine;
+use crossterm::terminal::{Clear, ClearType};
 use futures::stream::{FuturesUnordered, StreamExt, iter};
-use glob::{MatchOptions, glob_with};
+use glob::{MatchOptions, Pattern, glob_with};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use tokio::process::Command as AsyncCommand;
-use tokio::runtime;
+use tokio::{runtime, time};
 use tracing::{debug, error};
+use walkdir::WalkDir;
 
 use crate::command::CommandExt;
+use crate::config::GitStrategy;
 use crate::execution_context::ExecutionContext;
 use crate::step::Step;
 use crate::steps::emacs::Emacs;
-use crate::terminal::print_separator;
-use crate::utils::{PathExt, require};
+use crate::terminal::{is_dumb, print_separator, shell};
+use crate::utils::{PathExt, require, require_one, which};
 use crate::{HOME_DIR, error::SkipStep, terminal::print_warning};
 use etcetera::base_strategy::BaseStrategy;
 use rust_i18n::t;
@@ -28,8 +40,8 @@ use crate::XDG_DIRS;
 #[cfg(windows)]
 use crate::WINDOWS_DIRS;
 
-pub fn run_git_pull_or_fetch(ctx: &ExecutionContext) -> Result<()> {
-    let mut repos = RepoStep::try_new()?;
+pub fn run_git_pull_or_fetch(ctx: &ExecutionContext, statuses: &mut Vec<RepoStatus>) -> Result<()> {
+    let mut repos = RepoStep::try_new_any_vcs()?;
     let config = ctx.config();
 
     // handle built-in repos
@@ -92,9 +104,23 @@ pub fn run_git_pull_or_fetch(ctx: &ExecutionContext) -> Result<()> {
     }
 
     // Handle user-defined repos
-    if let Some(custom_git_repos) = config.git_repos() {
-        for git_repo in custom_git_repos {
-            repos.glob_insert(ctx, &shellexpand::tilde(git_repo));
+    for git_repo in config.git_repos() {
+        repos.glob_insert(ctx, &shellexpand::tilde(git_repo));
+    }
+
+    // Handle the repos found in the roots
+    for root in config.git_roots() {
+        let found = discover_repos_cached(
+            Path::new(shellexpand::tilde(root).as_ref()),
+            config.git_max_depth(),
+            config.git_discovery_cache(),
+        );
+        let mut any_repo = false;
+        for repo in found {
+            any_repo |= repos.insert_if_repo(ctx, repo);
+        }
+        if !any_repo {
+            repos.bad_patterns.push(root.clone());
         }
     }
 
@@ -116,15 +142,194 @@ pub fn run_git_pull_or_fetch(ctx: &ExecutionContext) -> Result<()> {
 
     print_separator(t!("Git repositories"));
 
-    repos.pull_or_fetch_repos(ctx)
+    repos.pull_or_fetch_repos(ctx, statuses)
+}
+
+/// Directories never searched for repositories by `discover_repos()`.
+const SKIPPED_DIRECTORIES: [&str; 2] = ["node_modules", "target"];
+
+/// File listing patterns of the directories not to search for repositories, relative to its
+/// directory. An empty one excludes its own directory.
+const IGNORE_FILE: &str = ".topgradeignore";
+
+/// The repositories found in a root by `discover_repos()`, reused until they expire.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedDiscovery {
+    root: PathBuf,
+    max_depth: usize,
+    /// Seconds since the epoch
+    discovered_at: u64,
+    repos: Vec<PathBuf>,
+}
+
+/// Where the repositories found in the roots are cached.
+fn discovery_cache_path() -> PathBuf {
+    #[cfg(unix)]
+    return XDG_DIRS.cache_dir().join("topgrade/git-repos.json");
+
+    #[cfg(windows)]
+    return WINDOWS_DIRS.cache_dir().join("topgrade/git-repos.json");
+}
+
+/// Like `discover_repos()`, reusing the repositories found less than `ttl` ago.
+fn discover_repos_cached(root: &Path, max_depth: usize, ttl: Duration) -> Vec<PathBuf> {
+    if ttl.is_zero() {
+        return discover_repos(root, max_depth);
+    }
+
+    let cache_path = discovery_cache_path();
+    let mut cache: Vec<CachedDiscovery> = fs::read_to_string(&cache_path)
+        .ok()
+        .and_then(|cache| serde_json::from_str(&cache).ok())
+        .unwrap_or_default();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    if let Some(cached) = cache.iter().find(|cached| {
+        cached.root == root && cached.max_depth == max_depth && now.saturating_sub(cached.discovered_at) < ttl.as_secs()
+    }) {
+        debug!("Using the repositories found in {} before", root.display());
+        return cached.repos.iter().filter(|repo| repo.exists()).cloned().collect();
+    }
+
+    let repos = discover_repos(root, max_depth);
+    cache.retain(|cached| cached.root != root || cached.max_depth != max_depth);
+    cache.push(CachedDiscovery {
+        root: root.to_path_buf(),
+        max_depth,
+        discovered_at: now,
+        repos: repos.clone(),
+    });
+    let saved = cache_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&cache_path, serde_json::to_string(&cache)?));
+    if let Err(e) = saved {
+        debug!("Failed to save {}: {e}", cache_path.display());
+    }
+
+    repos
+}
+
+/// Read the patterns of the ignore file of `directory`, if any.
+fn read_ignore_file(directory: &Path) -> Option<Vec<Pattern>> {
+    let contents = fs::read_to_string(directory.join(IGNORE_FILE)).ok()?;
+    Some(
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| match Pattern::new(line.trim_end_matches('/')) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    error!("Bad pattern {line} in {}: {e}", directory.join(IGNORE_FILE).display());
+                    None
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Find the Git, Jujutsu and Mercurial repositories in `root`, at most `max_depth` levels deep.
+///
+/// The repositories themselves are not searched, so their vendored submodules and nested
+/// repositories are left out, nor are the `SKIPPED_DIRECTORIES` and the directories listed
+/// in ignore files.
+fn discover_repos(root: &Path, max_depth: usize) -> Vec<PathBuf> {
+    let mut repos = Vec::new();
+    let mut ignores: Vec<(PathBuf, Vec<Pattern>)> = Vec::new();
+
+    let mut walker = WalkDir::new(root).max_depth(max_depth).into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                debug!("Error searching {} for repositories: {e}", root.display());
+                continue;
+            }
+        };
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+
+        let path = entry.path();
+        let skipped = entry.depth() > 0
+            && (entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| SKIPPED_DIRECTORIES.contains(&name))
+                || ignores.iter().any(|(directory, patterns)| {
+                    path.strip_prefix(directory).is_ok_and(|relative| {
+                        patterns.iter().any(|pattern| {
+                            pattern.matches_path(relative)
+                                || (!pattern.as_str().contains('/')
+                                    && path
+                                        .file_name()
+                                        .is_some_and(|name| pattern.matches_path(Path::new(name))))
+                        })
+                    })
+                }));
+        if skipped {
+            debug!("Not searching {} for repositories", path.display());
+            walker.skip_current_dir();
+            continue;
+        }
+
+        if Vcs::of_root(path).is_some() {
+            repos.push(path.to_path_buf());
+            walker.skip_current_dir();
+            continue;
+        }
+
+        match read_ignore_file(path) {
+            Some(patterns) if patterns.is_empty() => {
+                debug!("{} has an empty {IGNORE_FILE}, not searching it", path.display());
+                walker.skip_current_dir();
+            }
+            Some(patterns) => ignores.push((path.to_path_buf(), patterns)),
+            None => (),
+        }
+    }
+
+    repos
 }
 
 #[cfg(windows)]
 static PATH_PREFIX: &str = "\\\\?\\";
 
+/// A version control system of the repositories.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Vcs {
+    Git,
+    /// `jj`, whose repositories may be colocated with Git ones
+    Jujutsu,
+    /// `hg`
+    Mercurial,
+}
+
+impl Vcs {
+    /// The version control system of the repository rooted in `directory`, if any.
+    /// A colocated Jujutsu repository is a Jujutsu one.
+    fn of_root(directory: &Path) -> Option<Self> {
+        if directory.join(".jj").is_dir() {
+            Some(Self::Jujutsu)
+        } else if directory.join(".hg").is_dir() {
+            Some(Self::Mercurial)
+        } else if directory.join(".git").exists() {
+            Some(Self::Git)
+        } else {
+            None
+        }
+    }
+}
+
 pub struct RepoStep {
-    git: PathBuf,
-    repos: HashSet<PathBuf>,
+    git: Option<PathBuf>,
+    jj: Option<PathBuf>,
+    hg: Option<PathBuf>,
+    repos: HashMap<PathBuf, Vcs>,
     glob_match_options: MatchOptions,
     bad_patterns: Vec<String>,
 }
@@ -140,6 +345,20 @@ fn output_checked_utf8(output: Output) -> Result<()> {
     }
 }
 
+/// Whether some files of `repo` are stored with Git LFS, as set by any of its `.gitattributes`
+/// or by its `info/attributes`.
+async fn uses_lfs(git: &Path, repo: &Path) -> Result<bool> {
+    let output = AsyncCommand::new(git)
+        .stdin(Stdio::null())
+        .current_dir(repo)
+        .args(["ls-files", "--", ":(attr:filter=lfs)"])
+        .output()
+        .await?;
+    let uses_lfs = !output.stdout.is_empty();
+    output_checked_utf8(output)?;
+    Ok(uses_lfs)
+}
+
 fn get_revision<P: AsRef<Path>>(ctx: &ExecutionContext, git: &Path, repo: P, revision: &str) -> Option<String> {
     ctx.execute(git)
         .always()
@@ -177,10 +396,249 @@ fn should_fetch_default(current_branch: Option<&str>, default_branch: &str) -> b
     current_branch != Some(default_branch)
 }
 
+/// The state of a repository after pulling or fetching it, also in the machine-readable report.
+#[derive(Debug, Default, Serialize)]
+pub struct RepoStatus {
+    repo: PathBuf,
+    /// The current branch, `None` on a detached HEAD
+    branch: Option<String>,
+    /// The pulled commits were merged into the working copy, rather than only fetched
+    fast_forwarded: bool,
+    /// Commits pulled (or fetched into the default branch)
+    pulled: usize,
+    /// The revision of the working copy changed, which runs `on_change`
+    changed: bool,
+    /// Local commits not in the upstream branch
+    ahead: usize,
+    /// Upstream commits not in the local branch, left when fetching or when `pull --ff-only` refused
+    behind: usize,
+    /// Uncommitted changes to tracked files
+    dirty: bool,
+    diverged: bool,
+    detached: bool,
+    no_upstream: bool,
+    /// Not updated because some incoming commits are not signed by a trusted key
+    refused: bool,
+    failed: bool,
+}
+
+impl RepoStatus {
+    /// Whether the repository needs manual attention.
+    fn needs_attention(&self) -> bool {
+        self.failed
+            || self.refused
+            || self.dirty
+            || self.diverged
+            || self.detached
+            || self.no_upstream
+            || self.ahead > 0
+    }
+
+    /// Short descriptions of the notable states of the repository.
+    fn flags(&self) -> Vec<Cow<'static, str>> {
+        [
+            (self.failed, t!("failed")),
+            (self.refused, t!("refused")),
+            (self.diverged, t!("diverged")),
+            (self.dirty, t!("dirty")),
+            (self.detached, t!("detached")),
+            (self.no_upstream, t!("no upstream")),
+            (self.pulled > 0 && self.fast_forwarded, t!("fast-forwarded")),
+            (self.pulled > 0 && !self.fast_forwarded, t!("fetched")),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect()
+    }
+}
+
+/// How often the progress of the pulls is redrawn, to update the elapsed time.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Most repositories listed in the progress of the pulls.
+const PROGRESS_MAX_REPOS: usize = 8;
+
+/// A live view of the repositories being pulled, drawn on a terminal below their output.
+///
+/// The view is cleared before polling the pulls, so that everything they print, including
+/// the output of their commands, goes above it, and drawn again afterwards.
+struct PullProgress {
+    total: usize,
+    completed: usize,
+    in_flight: Vec<PathBuf>,
+    started: Instant,
+    /// How many lines of the view are on the screen
+    drawn: u16,
+}
+
+impl PullProgress {
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            completed: 0,
+            in_flight: Vec::new(),
+            started: Instant::now(),
+            drawn: 0,
+        }
+    }
+
+    fn start(&mut self, repo: &Path) {
+        self.in_flight.push(repo.to_path_buf());
+    }
+
+    fn finish(&mut self, repo: &Path) {
+        self.in_flight.retain(|in_flight| in_flight != repo);
+        self.completed += 1;
+    }
+
+    /// The lines of the view, fitting in `width` columns.
+    fn lines(&self, width: usize) -> Vec<String> {
+        let elapsed = self.started.elapsed().as_secs();
+        let mut lines = vec![
+            t!(
+                "{completed}/{total} repositories updated, {elapsed} elapsed",
+                completed = self.completed,
+                total = self.total,
+                elapsed = format!("{}:{:02}", elapsed / 60, elapsed % 60)
+            )
+            .to_string(),
+        ];
+        lines.extend(
+            self.in_flight
+                .iter()
+                .take(PROGRESS_MAX_REPOS)
+                .map(|repo| format!("  {}", repo.display())),
+        );
+        if self.in_flight.len() > PROGRESS_MAX_REPOS {
+            lines.push(format!(
+                "  {}",
+                t!("and {count} more", count = self.in_flight.len() - PROGRESS_MAX_REPOS)
+            ));
+        }
+
+        lines
+            .into_iter()
+            .map(|line| line.chars().take(width.saturating_sub(1)).collect())
+            .collect()
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let width = crossterm::terminal::size().map_or(80, |(width, _)| width);
+        let lines = self.lines(width.into());
+
+        let mut stdout = io::stdout();
+        for line in &lines {
+            writeln!(stdout, "{}", style(line).dim())?;
+        }
+        stdout.flush()?;
+        self.drawn = lines.len() as u16;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        if self.drawn > 0 {
+            crossterm::execute!(
+                io::stdout(),
+                MoveToPreviousLine(self.drawn),
+                Clear(ClearType::FromCursorDown)
+            )?;
+            self.drawn = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the `signature` of a commit, formatted as `%G? %GF %GP`, is good and made by a trusted key:
+/// one of the `trusted_keys`, or any key trusted by git when there are none.
+fn signature_trusted(signature: &str, trusted_keys: &[String]) -> bool {
+    let mut fields = signature.split_whitespace();
+    let status = fields.next();
+    let mut fingerprints = fields;
+
+    match status {
+        Some("G") if trusted_keys.is_empty() => true,
+        // Good signatures of keys unknown to git are trusted when listed
+        Some("G" | "U") => fingerprints.any(|fingerprint| {
+            trusted_keys
+                .iter()
+                .any(|trusted| trusted.eq_ignore_ascii_case(fingerprint))
+        }),
+        _ => false,
+    }
+}
+
+/// Parse the output of `git rev-list --left-right --count HEAD...@{upstream}` into `(ahead, behind)`.
+fn parse_ahead_behind(output: &str) -> Option<(usize, usize)> {
+    let mut counts = output.split_whitespace().map(str::parse);
+    match (counts.next(), counts.next()) {
+        (Some(Ok(ahead)), Some(Ok(behind))) => Some((ahead, behind)),
+        _ => None,
+    }
+}
+
+/// Format `statuses` as a table, with a row per repository.
+fn format_status_table(statuses: &[&RepoStatus]) -> String {
+    let header = [
+        t!("Repository"),
+        t!("Branch"),
+        t!("Pulled"),
+        t!("Ahead"),
+        t!("Behind"),
+        t!("State"),
+    ]
+    .map(Cow::into_owned);
+    let rows: Vec<[String; 6]> = statuses
+        .iter()
+        .map(|status| {
+            [
+                status.repo.display().to_string(),
+                status.branch.clone().unwrap_or_else(|| "-".to_string()),
+                status.pulled.to_string(),
+                status.ahead.to_string(),
+                status.behind.to_string(),
+                status.flags().join(", "),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.each_ref().map(|cell| cell.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            row.iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 impl RepoStep {
     /// Try to create a `RepoStep`, fail if `git` is not found.
     pub fn try_new() -> Result<Self> {
-        let git = require("git")?;
+        require("git")?;
+        Self::try_new_any_vcs()
+    }
+
+    /// Try to create a `RepoStep` also pulling Jujutsu and Mercurial repositories, fail if none
+    /// of `git`, `jj` and `hg` is found.
+    pub fn try_new_any_vcs() -> Result<Self> {
+        let git = which("git");
+        let jj = which("jj");
+        let hg = which("hg");
+        if git.is_none() && jj.is_none() && hg.is_none() {
+            require_one(["git", "jj", "hg"])?;
+        }
         let mut glob_match_options = MatchOptions::new();
 
         if cfg!(windows) {
@@ -189,14 +647,32 @@ impl RepoStep {
 
         Ok(Self {
             git,
-            repos: HashSet::new(),
+            jj,
+            hg,
+            repos: HashMap::new(),
             bad_patterns: Vec::new(),
             glob_match_options,
         })
     }
 
-    /// Try to get the root of the repo specified in `path`.
-    pub fn get_repo_root<P: AsRef<Path>>(&self, ctx: &ExecutionContext, path: P) -> Option<PathBuf> {
+    /// The `git` executable, which is found when there are Git repositories.
+    fn git(&self) -> Result<&Path> {
+        self.git.as_deref().ok_or_else(|| {
+            SkipStep(t!("Cannot find {binary_name} in PATH", binary_name = "\"git\"").to_string()).into()
+        })
+    }
+
+    /// The executable of `vcs`, if found.
+    fn vcs_executable(&self, vcs: Vcs) -> Option<&Path> {
+        match vcs {
+            Vcs::Git => self.git.as_deref(),
+            Vcs::Jujutsu => self.jj.as_deref(),
+            Vcs::Mercurial => self.hg.as_deref(),
+        }
+    }
+
+    /// Try to get the root of the repo specified in `path`, and its version control system.
+    fn get_repo_root<P: AsRef<Path>>(&self, ctx: &ExecutionContext, path: P) -> Option<(PathBuf, Vcs)> {
         match path.as_ref().canonicalize() {
             Ok(mut path) => {
                 debug_assert!(path.exists());
@@ -217,11 +693,21 @@ impl RepoStep {
 
                     debug!("Transformed path to {}", path_string);
 
-                    path_string
+                    PathBuf::from(path_string)
                 };
 
+                // Jujutsu and Mercurial repositories are recognized by their root directory,
+                // stopping at the root of a Git repository
+                for ancestor in path.ancestors() {
+                    match Vcs::of_root(ancestor) {
+                        Some(Vcs::Git) => break,
+                        Some(vcs) if self.vcs_executable(vcs).is_some() => return Some((ancestor.to_path_buf(), vcs)),
+                        _ => (),
+                    }
+                }
+
                 let output = ctx
-                    .execute(&self.git)
+                    .execute(self.git.as_ref()?)
                     .always()
                     .stdin(Stdio::null())
                     .current_dir(path)
@@ -229,7 +715,7 @@ impl RepoStep {
                     .output_checked_utf8()
                     .ok()
                     // trim the last newline char
-                    .map(|output| PathBuf::from(output.stdout.trim()));
+                    .map(|output| (PathBuf::from(output.stdout.trim()), Vcs::Git));
 
                 return output;
             }
@@ -249,29 +735,35 @@ impl RepoStep {
     ///
     /// Return the check result.
     pub fn insert_if_repo<P: AsRef<Path>>(&mut self, ctx: &ExecutionContext, path: P) -> bool {
-        if let Some(repo) = self.get_repo_root(ctx, path) {
-            self.repos.insert(repo);
+        if let Some((repo, vcs)) = self.get_repo_root(ctx, path) {
+            self.repos.insert(repo, vcs);
             true
         } else {
             false
         }
     }
 
-    /// Check if `repo` has a remote.
-    fn has_remotes<P: AsRef<Path>>(&self, ctx: &ExecutionContext, repo: P) -> Option<bool> {
+    /// Check if `repo`, managed by `vcs`, has a remote.
+    fn has_remotes<P: AsRef<Path>>(&self, ctx: &ExecutionContext, repo: P, vcs: Vcs) -> Option<bool> {
+        let args: &[&str] = match vcs {
+            Vcs::Git => &["remote", "-v"],
+            Vcs::Jujutsu => &["git", "remote", "list", "--ignore-working-copy"],
+            Vcs::Mercurial => &["paths"],
+        };
         let res = ctx
-            .execute(&self.git)
+            .execute(self.vcs_executable(vcs)?)
             .always()
             .stdin(Stdio::null())
             .current_dir(repo.as_ref())
-            .args(["remote", "-v"])
+            .args(args)
             .output_checked_utf8();
 
         res.map(|output| {
             output
                 .stdout
                 .lines()
-                // remote lines always have: "<name>\t<url> (<fetch|push>)"
+                // remote lines always have a name and a URL: "<name>\t<url> (<fetch|push>)" for git,
+                // "<name> <url>" for jj and "<name> = <url>" for hg
                 .any(|line| {
                     // split into at least 2 fields: name + url
                     let mut parts = line.split_whitespace();
@@ -296,8 +788,9 @@ impl RepoStep {
     /// when known locally, otherwise queried from the remote with `ls-remote`.
     /// Returns `None` when neither can be determined.
     fn resolve_default_branch<P: AsRef<Path>>(&self, ctx: &ExecutionContext, repo: P) -> Option<(String, String)> {
+        let git = self.git().ok()?;
         let remotes = ctx
-            .execute(&self.git)
+            .execute(git)
             .always()
             .stdin(Stdio::null())
             .current_dir(repo.as_ref())
@@ -318,7 +811,7 @@ impl RepoStep {
 
         // Prefer the locally-known default branch (no network access).
         let local_head = ctx
-            .execute(&self.git)
+            .execute(git)
             .always()
             .stdin(Stdio::null())
             .current_dir(repo.as_ref())
@@ -338,7 +831,7 @@ impl RepoStep {
 
         // Fall back to asking the remote when its HEAD is not set locally.
         let symref = ctx
-            .execute(&self.git)
+            .execute(git)
             .always()
             .stdin(Stdio::null())
             .current_dir(repo.as_ref())
@@ -403,19 +896,238 @@ impl RepoStep {
     #[cfg(unix)]
     pub fn remove<P: AsRef<Path>>(&mut self, path: P) {
         let _removed = self.repos.remove(path.as_ref());
-        debug_assert!(_removed);
+        debug_assert!(_removed.is_some());
+    }
+
+    /// Run git with `args` in `repo`, and get its output.
+    fn git_stdout(&self, ctx: &ExecutionContext, repo: &Path, args: &[&str]) -> Option<String> {
+        ctx.execute(self.git().ok()?)
+            .always()
+            .stdin(Stdio::null())
+            .current_dir(repo)
+            .args(args)
+            .output_checked_utf8()
+            .map(|output| output.stdout)
+            .ok()
+    }
+
+    /// Whether some local commits of `repo`, which are not in its upstream branch, were pushed
+    /// to another remote branch: rebasing them would rewrite published history.
+    fn has_published_local_commits(&self, ctx: &ExecutionContext, repo: &Path) -> bool {
+        let count = |args: &[&str]| {
+            self.git_stdout(ctx, repo, args)
+                .and_then(|count| count.trim().parse::<usize>().ok())
+        };
+        let local = count(&["rev-list", "--count", "@{upstream}..HEAD"]);
+        let unpublished = count(&["rev-list", "--count", "@{upstream}..HEAD", "--not", "--remotes"]);
+
+        matches!((local, unpublished), (Some(local), Some(unpublished)) if local != unpublished)
+    }
+
+    /// Abort the rebase left in progress in `repo` by a failed `pull --rebase`, which also
+    /// restores the changes stashed by `--autostash`.
+    fn abort_rebase(&self, ctx: &ExecutionContext, repo: &Path) {
+        let in_progress = ["rebase-merge", "rebase-apply"].iter().any(|path| {
+            self.git_stdout(ctx, repo, &["rev-parse", "--git-path", path])
+                .is_some_and(|path| repo.join(path.trim()).exists())
+        });
+        let Ok(git) = self.git() else {
+            return;
+        };
+        if !in_progress {
+            return;
+        }
+
+        if let Err(e) = ctx
+            .execute(git)
+            .always()
+            .stdin(Stdio::null())
+            .current_dir(repo)
+            .args(["rebase", "--abort"])
+            .output_checked()
+        {
+            error!("Failed to abort the rebase of {}: {e}", repo.display());
+        }
+    }
+
+    /// Fetch `repo`, and only update it with `strategy` when all the incoming commits are signed
+    /// by a trusted key. Sets `refused` otherwise.
+    async fn pull_verified(
+        &self,
+        ctx: &ExecutionContext<'_>,
+        repo: &Path,
+        strategy: GitStrategy,
+        refused: &mut bool,
+    ) -> Result<()> {
+        let executable = self.git()?;
+        let git = |args: &[&str]| {
+            let mut command = AsyncCommand::new(executable);
+            command.stdin(Stdio::null()).current_dir(repo).args(args);
+            command
+        };
+
+        output_checked_utf8(git(&["fetch", "--recurse-submodules"]).output().await?)?;
+        let upstream = self
+            .git_stdout(ctx, repo, &["rev-parse", "@{upstream}"])
+            .ok_or_else(|| eyre!("No upstream branch for {}", repo.display()))?;
+        let upstream = upstream.trim();
+
+        let untrusted = self.untrusted_commits(ctx, repo, &format!("HEAD..{upstream}"))?;
+        if !untrusted.is_empty() {
+            *refused = true;
+            return Err(eyre!(
+                "Refusing to update {}, commits not signed by a trusted key: {}",
+                repo.display(),
+                untrusted.join(", ")
+            ));
+        }
+
+        // Pull like the other repositories, with the Git arguments, but from the verified commit
+        // fetched above, so that no other commit can come in
+        let mut command = git(match strategy {
+            GitStrategy::FfOnly | GitStrategy::Fetch => &["pull", "--ff-only", "--recurse-submodules"],
+            GitStrategy::Rebase => &["pull", "--rebase", "--recurse-submodules"],
+            GitStrategy::AutostashRebase => &["pull", "--rebase", "--autostash", "--recurse-submodules"],
+        });
+        if let Some(extra_arguments) = ctx.config().git_arguments() {
+            command.args(extra_arguments.split_whitespace());
+        }
+        command.args([".", upstream]);
+        output_checked_utf8(command.output().await?)
     }
 
-    /// Try to pull a repo, or fetch it if `fetch_only` is enabled.
-    async fn pull_or_fetch_repo<P: AsRef<Path>>(&self, ctx: &ExecutionContext<'_>, repo: P) -> Result<()> {
-        let is_fetch_only = ctx.config().git_fetch_only();
+    /// Follow up on a pull of `repo` from `before` to `after`: sync the URLs of the submodules
+    /// and initialize the new ones when `.gitmodules` changed, and download the LFS objects of
+    /// a repository using LFS.
+    async fn update_submodules_and_lfs(&self, repo: &Path, before: &str, after: &str) -> Result<()> {
+        let executable = self.git()?;
+        let git = |args: &[&str]| {
+            let mut command = AsyncCommand::new(executable);
+            command.stdin(Stdio::null()).current_dir(repo).args(args);
+            command
+        };
+
+        // `diff --quiet` exits with 1 when there are differences
+        let submodules_changed = repo.join(".gitmodules").exists()
+            && git(&["diff", "--quiet", before, after, "--", ".gitmodules"])
+                .status()
+                .await?
+                .code()
+                == Some(1);
+        if submodules_changed {
+            debug!("Submodules of {} changed, syncing them", repo.display());
+            output_checked_utf8(git(&["submodule", "sync", "--recursive"]).output().await?)?;
+            output_checked_utf8(git(&["submodule", "update", "--init", "--recursive"]).output().await?)?;
+        }
+
+        if uses_lfs(executable, repo).await? {
+            if git(&["lfs", "version"]).output().await?.status.success() {
+                output_checked_utf8(git(&["lfs", "pull"]).output().await?)?;
+            } else {
+                print_warning(t!("{repo} uses Git LFS, which is not installed", repo = repo.display()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The commits of `range` in `repo` which are not signed by a trusted key, abbreviated.
+    fn untrusted_commits(&self, ctx: &ExecutionContext, repo: &Path, range: &str) -> Result<Vec<String>> {
+        let trusted_keys = ctx.config().git_trusted_keys(repo);
+
+        let mut command = ctx.execute(self.git()?).always();
+        command.stdin(Stdio::null()).current_dir(repo);
+        if let Some(allowed_signers) = ctx.config().git_allowed_signers(repo) {
+            command.arg("-c").arg(format!(
+                "gpg.ssh.allowedSignersFile={}",
+                shellexpand::tilde(&allowed_signers)
+            ));
+        }
+        let output = command
+            .args(["log", "--format=%h %G? %GF %GP", range])
+            .output_checked_utf8()?;
+
+        Ok(output
+            .stdout
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .filter(|(_, signature)| !signature_trusted(signature, &trusted_keys))
+            .map(|(commit, _)| commit.to_string())
+            .collect())
+    }
+
+    /// Get the state of `repo` after pulling or fetching it, which moved `tracked_revision`
+    /// from `before` to `after`.
+    fn repo_status(
+        &self,
+        ctx: &ExecutionContext,
+        repo: &Path,
+        before: Option<&str>,
+        after: Option<&str>,
+        fetching: bool,
+    ) -> RepoStatus {
+        let git = |args: &[&str]| self.git_stdout(ctx, repo, args);
+
+        let branch = self.git().ok().and_then(|git| get_current_branch(ctx, git, repo));
+        let pulled = match (before, after) {
+            (Some(before), Some(after)) if before != after => {
+                // Leave out the local commits that were rebased
+                git(&[
+                    "rev-list",
+                    "--count",
+                    "--right-only",
+                    "--cherry-pick",
+                    &format!("{before}...{after}"),
+                ])
+                .and_then(|count| count.trim().parse().ok())
+                .unwrap_or_default()
+            }
+            _ => 0,
+        };
+        let ahead_behind = if branch.is_some() {
+            git(&["rev-list", "--left-right", "--count", "HEAD...@{upstream}"])
+                .and_then(|output| parse_ahead_behind(&output))
+        } else {
+            None
+        };
+        let (ahead, behind) = ahead_behind.unwrap_or_default();
+
+        RepoStatus {
+            repo: repo.to_path_buf(),
+            detached: branch.is_none(),
+            no_upstream: branch.is_some() && ahead_behind.is_none(),
+            branch,
+            fast_forwarded: !fetching && pulled > 0,
+            pulled,
+            changed: false,
+            ahead,
+            behind,
+            dirty: git(&["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|output| !output.trim().is_empty()),
+            diverged: ahead > 0 && behind > 0,
+            refused: false,
+            failed: false,
+        }
+    }
+
+    /// Try to pull a repo, or fetch it, depending on its strategy.
+    ///
+    /// Returns the state of the repo afterwards along with the result.
+    async fn pull_or_fetch_repo<P: AsRef<Path>>(
+        &self,
+        ctx: &ExecutionContext<'_>,
+        repo: P,
+    ) -> Result<(RepoStatus, Result<()>)> {
+        let git = self.git()?;
+        let mut strategy = ctx.config().git_strategy(repo.as_ref());
+        let is_fetch_only = strategy == GitStrategy::Fetch;
 
         // When enabled and the repo is not on its default branch, fetch the
         // default branch into its local ref instead of running a pull/fetch that
         // would fail. `None` keeps the normal behavior.
         let fallback = if ctx.config().git_fallback_to_fetch_default() {
             self.resolve_default_branch(ctx, &repo).filter(|(_, default_branch)| {
-                let current_branch = get_current_branch(ctx, &self.git, &repo);
+                let current_branch = get_current_branch(ctx, git, &repo);
                 should_fetch_default(current_branch.as_deref(), default_branch)
             })
         } else {
@@ -428,40 +1140,86 @@ impl RepoStep {
             Some((_, default_branch)) => default_branch.as_str(),
             None => "HEAD",
         };
-        let before_revision = get_revision(ctx, &self.git, &repo, tracked_revision);
+        let before_revision = get_revision(ctx, git, &repo, tracked_revision);
 
         let fetching = is_fetch_only || fallback.is_some();
 
+        if matches!(strategy, GitStrategy::Rebase | GitStrategy::AutostashRebase)
+            && !fetching
+            && self.has_published_local_commits(ctx, repo.as_ref())
+        {
+            print_warning(t!(
+                "Not rebasing {repo}, its local commits were pushed: pulling with --ff-only",
+                repo = repo.as_ref().display()
+            ));
+            strategy = GitStrategy::FfOnly;
+        }
+
         if ctx.config().verbose() {
             let action = if fetching { t!("Fetching") } else { t!("Pulling") };
             println!("{} {}", style(action).cyan().bold(), repo.as_ref().display());
         }
 
-        let mut command = AsyncCommand::new(&self.git);
-        command.stdin(Stdio::null()).current_dir(&repo);
-
-        if let Some((remote, default_branch)) = &fallback {
-            // `<branch>:<branch>` updates the local branch fast-forward-only, so a
-            // diverged local default fails instead of being force-overwritten.
-            let refspec = format!("{default_branch}:{default_branch}");
-            command.args(["fetch", "--recurse-submodules", remote, &refspec]);
-        } else if is_fetch_only {
-            command.args(["fetch", "--recurse-submodules"]);
+        let verify = !fetching && ctx.config().git_verify_signatures(repo.as_ref());
+        let mut refused = false;
+        let mut result = if verify {
+            self.pull_verified(ctx, repo.as_ref(), strategy, &mut refused).await
         } else {
-            command.args(["pull", "--ff-only", "--recurse-submodules"]);
-        }
+            let mut command = AsyncCommand::new(git);
+            command.stdin(Stdio::null()).current_dir(&repo);
+
+            if let Some((remote, default_branch)) = &fallback {
+                // `<branch>:<branch>` updates the local branch fast-forward-only, so a
+                // diverged local default fails instead of being force-overwritten.
+                let refspec = format!("{default_branch}:{default_branch}");
+                command.args(["fetch", "--recurse-submodules", remote, &refspec]);
+            } else {
+                match strategy {
+                    GitStrategy::FfOnly => command.args(["pull", "--ff-only", "--recurse-submodules"]),
+                    GitStrategy::Rebase => command.args(["pull", "--rebase", "--recurse-submodules"]),
+                    GitStrategy::AutostashRebase => {
+                        command.args(["pull", "--rebase", "--autostash", "--recurse-submodules"])
+                    }
+                    GitStrategy::Fetch => command.args(["fetch", "--recurse-submodules"]),
+                };
+            }
 
-        if let Some(extra_arguments) = ctx.config().git_arguments() {
-            command.args(extra_arguments.split_whitespace());
-        }
+            if let Some(extra_arguments) = ctx.config().git_arguments() {
+                command.args(extra_arguments.split_whitespace());
+            }
 
-        let output = command.output().await?;
-        let result = output_checked_utf8(output).wrap_err_with(|| {
+            output_checked_utf8(command.output().await?)
+        }
+        .wrap_err_with(|| {
             let action = if fetching { "fetch" } else { "pull" };
             format!("Failed to {} {}", action, repo.as_ref().display())
         });
+        if result.is_err() && !fetching && matches!(strategy, GitStrategy::Rebase | GitStrategy::AutostashRebase) {
+            self.abort_rebase(ctx, repo.as_ref());
+        }
 
-        if result.is_err() {
+        let after_revision = get_revision(ctx, git, repo.as_ref(), tracked_revision);
+        if let (Ok(()), false, Some(before), Some(after)) = (&result, fetching, &before_revision, &after_revision)
+            && before != after
+        {
+            result = self
+                .update_submodules_and_lfs(repo.as_ref(), before, after)
+                .await
+                .wrap_err_with(|| format!("Failed to update the submodules of {}", repo.as_ref().display()));
+        }
+        let mut status = self.repo_status(
+            ctx,
+            repo.as_ref(),
+            before_revision.as_deref(),
+            after_revision.as_deref(),
+            fetching,
+        );
+        status.failed = result.is_err() && !refused;
+        status.refused = refused;
+
+        if refused {
+            println!("{} {}", style(t!("Refused")).red().bold(), repo.as_ref().display());
+        } else if result.is_err() {
             let action = if fetching { t!("fetching") } else { t!("pulling") };
             println!(
                 "{} {} {}",
@@ -470,13 +1228,11 @@ impl RepoStep {
                 repo.as_ref().display()
             );
         } else {
-            let after_revision = get_revision(ctx, &self.git, repo.as_ref(), tracked_revision);
-
             match (&before_revision, &after_revision) {
                 (Some(before), Some(after)) if before != after => {
                     println!("{} {}", style(t!("Changed")).yellow().bold(), repo.as_ref().display());
 
-                    ctx.execute(&self.git)
+                    ctx.execute(git)
                         .always()
                         .stdin(Stdio::null())
                         .current_dir(&repo)
@@ -489,6 +1245,8 @@ impl RepoStep {
                         ])
                         .status_checked()?;
                     println!();
+
+                    status.changed = true;
                 }
                 _ => {
                     if ctx.config().verbose() {
@@ -498,27 +1256,189 @@ impl RepoStep {
             }
         }
 
-        result
+        Ok((status, result))
+    }
+
+    /// Fetch a Jujutsu repository with `jj git fetch`, or pull and update a Mercurial one with
+    /// `hg pull --update`.
+    ///
+    /// Returns the state of the repo afterwards along with the result.
+    async fn pull_jj_or_hg_repo(
+        &self,
+        ctx: &ExecutionContext<'_>,
+        repo: &Path,
+        vcs: Vcs,
+    ) -> Result<(RepoStatus, Result<()>)> {
+        let executable = self
+            .vcs_executable(vcs)
+            .ok_or_else(|| eyre!("No executable for {vcs:?}"))?;
+        let run = |args: &[&str]| {
+            ctx.execute(executable)
+                .always()
+                .stdin(Stdio::null())
+                .current_dir(repo)
+                .args(args)
+                .output_checked_utf8()
+                .map(|output| output.stdout)
+        };
+
+        // The fetched remote bookmarks for jj, the local tip for hg
+        let revision = || {
+            match vcs {
+                Vcs::Jujutsu => run(&[
+                    "log",
+                    "--no-graph",
+                    "--ignore-working-copy",
+                    "--revisions",
+                    "remote_bookmarks()",
+                    "--template",
+                    "commit_id ++ \"\\n\"",
+                ])
+                .map(|output| output.lines().sorted().join("|")),
+                _ => run(&["log", "--rev", "tip", "--template", "{rev}"]).map(|output| output.trim().to_string()),
+            }
+            .map_err(|e| error!("Error getting revision for {}: {e}", repo.display()))
+            .ok()
+        };
+
+        if ctx.config().verbose() {
+            let action = if vcs == Vcs::Jujutsu {
+                t!("Fetching")
+            } else {
+                t!("Pulling")
+            };
+            println!("{} {}", style(action).cyan().bold(), repo.display());
+        }
+
+        let before_revision = revision();
+        let mut command = AsyncCommand::new(executable);
+        command.stdin(Stdio::null()).current_dir(repo);
+        match vcs {
+            Vcs::Jujutsu => command.args(["git", "fetch"]),
+            _ => command.args(["pull", "--update"]),
+        };
+        let result = output_checked_utf8(command.output().await?).wrap_err_with(|| {
+            let action = if vcs == Vcs::Jujutsu { "fetch" } else { "pull" };
+            format!("Failed to {} {}", action, repo.display())
+        });
+        let after_revision = revision();
+
+        // The incoming commits are only listed when the commits before are still there, which is
+        // not the case for jj after a force-push
+        let changed = matches!((&before_revision, &after_revision), (Some(before), Some(after)) if before != after);
+        let incoming = match (&before_revision, &after_revision) {
+            (Some(before), Some(after)) if changed => match vcs {
+                Vcs::Jujutsu => {
+                    let before = if before.is_empty() { "none()" } else { before };
+                    run(&[
+                        "log",
+                        "--no-graph",
+                        "--ignore-working-copy",
+                        "--revisions",
+                        &format!("::remote_bookmarks() ~ ::({before})"),
+                        "--template",
+                        "commit_id.short() ++ \" \" ++ description.first_line() ++ \"\\n\"",
+                    ])
+                }
+                _ => run(&[
+                    "log",
+                    "--rev",
+                    &format!("rev({before}):rev({after}) - rev({before})"),
+                    "--template",
+                    "{node|short} {desc|firstline}\\n",
+                ]),
+            }
+            .map_err(|e| debug!("Failed to list the incoming commits of {}: {e}", repo.display()))
+            .ok(),
+            _ => None,
+        };
+        let pulled = incoming.as_deref().map_or(0, |incoming| incoming.lines().count());
+        let count = |args: &[&str]| run(args).map_or(0, |output| output.lines().count());
+
+        let mut status = RepoStatus {
+            repo: repo.to_path_buf(),
+            fast_forwarded: vcs == Vcs::Mercurial && changed,
+            pulled,
+            failed: result.is_err(),
+            ..Default::default()
+        };
+        match vcs {
+            Vcs::Jujutsu => {
+                // The closest bookmark below the working copy, which is a commit of its own
+                status.branch = run(&[
+                    "log",
+                    "--no-graph",
+                    "--ignore-working-copy",
+                    "--limit",
+                    "1",
+                    "--revisions",
+                    "heads(::@ & bookmarks())",
+                    "--template",
+                    "local_bookmarks.map(|bookmark| bookmark.name()).join(\",\")",
+                ])
+                .ok()
+                .map(|branch| branch.trim().to_string())
+                .filter(|branch| !branch.is_empty());
+                status.dirty = run(&["log", "--no-graph", "--revisions", "@", "--template", "empty"])
+                    .is_ok_and(|empty| empty.trim() == "false");
+                status.ahead = count(&[
+                    "log",
+                    "--no-graph",
+                    "--ignore-working-copy",
+                    "--revisions",
+                    "remote_bookmarks()..@-",
+                    "--template",
+                    "commit_id ++ \"\\n\"",
+                ]);
+            }
+            _ => {
+                status.branch = run(&["branch"]).ok().map(|branch| branch.trim().to_string());
+                status.dirty = run(&["status", "--modified", "--added", "--removed", "--deleted"])
+                    .is_ok_and(|output| !output.trim().is_empty());
+                // Draft commits were not pushed
+                status.ahead = count(&["log", "--rev", "draft() and ::.", "--template", "{node}\\n"]);
+            }
+        }
+
+        if result.is_err() {
+            let action = if vcs == Vcs::Jujutsu {
+                t!("fetching")
+            } else {
+                t!("pulling")
+            };
+            println!("{} {} {}", style(t!("Failed")).red().bold(), action, repo.display());
+        } else if changed {
+            println!("{} {}", style(t!("Changed")).yellow().bold(), repo.display());
+            if let Some(incoming) = incoming {
+                println!("{incoming}");
+            }
+
+            status.changed = true;
+        } else if ctx.config().verbose() {
+            println!("{} {}", style(t!("Up-to-date")).green().bold(), repo.display());
+        }
+
+        Ok((status, result))
     }
 
-    /// Pulls or fetches the repositories specified in `self.repos`, depending on `fetch_only`.
+    /// Pulls or fetches the repositories specified in `self.repos`, depending on their strategy.
     ///
     /// # NOTE
     /// This function will create an async runtime and do the real job so the
     /// function itself is not async.
-    fn pull_or_fetch_repos(&self, ctx: &ExecutionContext) -> Result<()> {
-        let is_fetch_only = ctx.config().git_fetch_only();
-
+    fn pull_or_fetch_repos(&self, ctx: &ExecutionContext, statuses: &mut Vec<RepoStatus>) -> Result<()> {
         if ctx.run_type().dry() {
             let fallback_enabled = ctx.config().git_fallback_to_fetch_default();
-            self.repos.iter().for_each(|repo| {
+            self.repos.iter().for_each(|(repo, vcs)| {
                 // A repo off its default branch would be fetched, not pulled.
-                let would_fetch = is_fetch_only
-                    || (fallback_enabled
+                let would_fetch = *vcs == Vcs::Jujutsu
+                    || *vcs == Vcs::Git && ctx.config().git_strategy(repo) == GitStrategy::Fetch
+                    || (*vcs == Vcs::Git
+                        && fallback_enabled
                         && self
                             .resolve_default_branch(ctx, repo)
                             .is_some_and(|(_, default_branch)| {
-                                let current_branch = get_current_branch(ctx, &self.git, repo);
+                                let current_branch = self.git().ok().and_then(|git| get_current_branch(ctx, git, repo));
                                 should_fetch_default(current_branch.as_deref(), &default_branch)
                             }));
                 let message = if would_fetch {
@@ -540,10 +1460,10 @@ impl RepoStep {
             );
         }
 
-        let futures_iterator = self
+        let repos: Vec<_> = self
             .repos
             .iter()
-            .filter(|repo| match self.has_remotes(ctx, repo) {
+            .filter(|(repo, vcs)| match self.has_remotes(ctx, repo, **vcs) {
                 Some(false) => {
                     println!(
                         "{} {} {}",
@@ -555,7 +1475,25 @@ impl RepoStep {
                 }
                 _ => true, // repo has remotes or command to check for remotes has failed. proceed to pull anyway.
             })
-            .map(|repo| self.pull_or_fetch_repo(ctx, repo));
+            .collect();
+
+        // Show the progress on terminals, the results are printed as they come otherwise
+        let progress = (io::stdout().is_terminal() && !is_dumb()).then(|| Mutex::new(PullProgress::new(repos.len())));
+        let progress = progress.as_ref();
+
+        let futures_iterator = repos.into_iter().map(|(repo, vcs)| async move {
+            if let Some(progress) = progress {
+                progress.lock().unwrap().start(repo);
+            }
+            let result = match vcs {
+                Vcs::Git => self.pull_or_fetch_repo(ctx, repo).await,
+                Vcs::Jujutsu | Vcs::Mercurial => self.pull_jj_or_hg_repo(ctx, repo, *vcs).await,
+            };
+            if let Some(progress) = progress {
+                progress.lock().unwrap().finish(repo);
+            }
+            result
+        });
 
         let stream_of_futures = if let Some(limit) = ctx.config().git_concurrency_limit() {
             iter(futures_iterator).buffer_unordered(limit).boxed()
@@ -564,16 +1502,284 @@ impl RepoStep {
         };
 
         let basic_rt = runtime::Runtime::new()?;
-        let results = basic_rt.block_on(async { stream_of_futures.collect::<Vec<Result<()>>>().await });
+        let results = match progress {
+            Some(progress) => basic_rt.block_on(async {
+                let mut stream_of_futures = stream_of_futures;
+                let mut ticks = time::interval(PROGRESS_INTERVAL);
+                let mut results = Vec::new();
+                poll_fn(|cx| {
+                    // Wake up regularly to update the elapsed time
+                    while ticks.poll_tick(cx).is_ready() {}
+
+                    progress.lock().unwrap().clear().ok();
+                    let poll = loop {
+                        match stream_of_futures.poll_next_unpin(cx) {
+                            Poll::Ready(Some(result)) => results.push(result),
+                            Poll::Ready(None) => break Poll::Ready(()),
+                            Poll::Pending => break Poll::Pending,
+                        }
+                    };
+                    if poll.is_pending() {
+                        progress.lock().unwrap().draw().ok();
+                    }
+                    poll
+                })
+                .await;
+                results
+            }),
+            None => basic_rt.block_on(async { stream_of_futures.collect::<Vec<_>>().await }),
+        };
 
-        let error = results.into_iter().find(std::result::Result::is_err);
-        error.unwrap_or(Ok(()))
+        statuses.clear();
+        let mut error = None;
+        for result in results {
+            match result {
+                Ok((status, result)) => {
+                    statuses.push(status);
+                    error = error.or(result.err());
+                }
+                Err(e) => error = error.or(Some(e)),
+            }
+        }
+        statuses.sort_by(|a, b| a.repo.cmp(&b.repo));
+
+        // The `on_change` commands run one after the other once all the pulls are done, so that
+        // they neither hold up the pulls nor mix their output
+        for status in statuses.iter_mut().filter(|status| status.changed) {
+            error = error.or(run_on_change(ctx, status).err());
+        }
+        print_statuses(ctx, statuses);
+
+        error.map_or(Ok(()), Err)
+    }
+}
+
+/// Run the `on_change` command of the repository of `status` after its revision changed,
+/// marking it failed when the command fails.
+fn run_on_change(ctx: &ExecutionContext, status: &mut RepoStatus) -> Result<()> {
+    let Some(command) = ctx.config().git_on_change(&status.repo) else {
+        return Ok(());
+    };
+
+    let result = ctx
+        .execute(shell())
+        .stdin(Stdio::null())
+        .current_dir(&status.repo)
+        .arg("-c")
+        .arg(&command)
+        .status_checked()
+        .wrap_err_with(|| format!("Failed to run `{}` in {}", command, status.repo.display()));
+    if result.is_err() {
+        status.failed = true;
+        println!(
+            "{} {} {}",
+            style(t!("Failed")).red().bold(),
+            t!("running on_change in"),
+            status.repo.display()
+        );
+    }
+
+    result
+}
+
+/// Print the table of the repositories that were updated or need attention (all of them when verbose).
+fn print_statuses(ctx: &ExecutionContext, statuses: &[RepoStatus]) {
+    let shown: Vec<_> = statuses
+        .iter()
+        .filter(|status| ctx.config().verbose() || status.pulled > 0 || status.changed || status.needs_attention())
+        .collect();
+    if !shown.is_empty() {
+        println!("\n{}\n", format_status_table(&shown));
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::should_fetch_default;
+    use super::*;
+
+    #[test]
+    fn discovers_repos() {
+        let root = tempfile::tempdir().unwrap();
+        for directory in [
+            "a/.git",
+            "a/vendor/nested/.git",
+            "group/b/.git",
+            "node_modules/c/.git",
+            "ignored/d/.git",
+            "group/skipped/e/.git",
+            "empty/f/.git",
+            "too/deep/for/g/.git",
+            "h/.hg",
+            "i/.jj",
+        ] {
+            fs::create_dir_all(root.path().join(directory)).unwrap();
+        }
+        fs::write(root.path().join(IGNORE_FILE), "# comment\nignored\ngroup/skipped\n").unwrap();
+        fs::write(root.path().join("empty").join(IGNORE_FILE), "").unwrap();
+
+        let mut repos = discover_repos(root.path(), 3);
+        repos.sort();
+        assert_eq!(
+            repos,
+            [
+                root.path().join("a"),
+                root.path().join("group/b"),
+                root.path().join("h"),
+                root.path().join("i")
+            ]
+        );
+    }
+
+    #[test]
+    fn progress_lines() {
+        let mut progress = PullProgress::new(12);
+        for i in 0..10 {
+            progress.start(Path::new(&format!("/src/repository-{i}")));
+        }
+        progress.finish(Path::new("/src/repository-0"));
+
+        let lines = progress.lines(16);
+        assert_eq!(lines.len(), 1 + PROGRESS_MAX_REPOS + 1);
+        assert!(lines[0].starts_with("1/12"));
+        assert_eq!(lines[1], "  /src/reposito");
+        assert_eq!(lines[PROGRESS_MAX_REPOS + 1], "  and 1 more");
+    }
+
+    #[test]
+    fn trusts_signatures() {
+        let fingerprint = "SHA256:dqW5jKgSrQd2ZMNcPqqvKz3nZLAl2yTPs3BXrVPCJvE".to_string();
+        assert!(signature_trusted("G ABCD ABCD", &[]));
+        assert!(!signature_trusted("U ABCD ABCD", &[]));
+        assert!(!signature_trusted("N  ", &[]));
+        assert!(signature_trusted(
+            "U SHA256:dqW5jKgSrQd2ZMNcPqqvKz3nZLAl2yTPs3BXrVPCJvE ",
+            std::slice::from_ref(&fingerprint)
+        ));
+        assert!(!signature_trusted("G 1234 1234", &[fingerprint]));
+        assert!(signature_trusted("G 1234 abcd", &["ABCD".to_string()]));
+    }
+
+    #[test]
+    fn parses_ahead_behind() {
+        assert_eq!(parse_ahead_behind("2\t5\n"), Some((2, 5)));
+        assert_eq!(parse_ahead_behind(""), None);
+    }
+
+    #[test]
+    fn formats_the_status_table() {
+        let status = RepoStatus {
+            repo: PathBuf::from("/src/topgrade"),
+            branch: Some("main".to_string()),
+            ahead: 1,
+            behind: 3,
+            dirty: true,
+            diverged: true,
+            failed: true,
+            ..Default::default()
+        };
+        let detached = RepoStatus {
+            repo: PathBuf::from("/src/dotfiles"),
+            detached: true,
+            ..Default::default()
+        };
+        let pulled = RepoStatus {
+            repo: PathBuf::from("/src/linux"),
+            branch: Some("master".to_string()),
+            fast_forwarded: true,
+            pulled: 12,
+            ..Default::default()
+        };
+        let fetched = RepoStatus {
+            repo: PathBuf::from("/src/rust"),
+            branch: Some("feature".to_string()),
+            pulled: 4,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            format_status_table(&[&status, &detached, &pulled, &fetched]),
+            "Repository     Branch   Pulled  Ahead  Behind  State\n\
+             /src/topgrade  main     0       1      3       failed, diverged, dirty\n\
+             /src/dotfiles  -        0       0      0       detached\n\
+             /src/linux     master   12      0      0       fast-forwarded\n\
+             /src/rust      feature  4       0      0       fetched"
+        );
+    }
+
+    #[test]
+    fn serializes_the_statuses() {
+        let status = RepoStatus {
+            repo: PathBuf::from("/src/topgrade"),
+            branch: Some("main".to_string()),
+            pulled: 3,
+            ahead: 1,
+            dirty: true,
+            ..Default::default()
+        };
+        let detached = RepoStatus {
+            repo: PathBuf::from("/src/dotfiles"),
+            detached: true,
+            ..Default::default()
+        };
+
+        let json = serde_json::to_value([&status, &detached]).unwrap();
+        assert_eq!(json[0]["repo"], "/src/topgrade");
+        assert_eq!(json[0]["branch"], "main");
+        assert_eq!(json[0]["pulled"], 3);
+        assert_eq!(json[0]["ahead"], 1);
+        assert_eq!(json[0]["dirty"], true);
+        assert_eq!(json[0]["diverged"], false);
+        assert_eq!(json[0]["no_upstream"], false);
+        assert_eq!(json[1]["branch"], serde_json::Value::Null);
+        assert_eq!(json[1]["detached"], true);
+    }
+
+    #[test]
+    fn skips_git_repositories_without_git() {
+        let repos = RepoStep {
+            git: None,
+            jj: Some(PathBuf::from("jj")),
+            hg: None,
+            repos: HashMap::new(),
+            bad_patterns: Vec::new(),
+            glob_match_options: MatchOptions::new(),
+        };
+        assert!(repos.git().unwrap_err().downcast_ref::<SkipStep>().is_some());
+    }
+
+    #[test]
+    fn finds_lfs_in_nested_attributes() {
+        let repo = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            #[expect(clippy::disallowed_methods)]
+            let mut command = std::process::Command::new("git");
+            command.current_dir(repo.path()).args(args).output_checked().unwrap();
+        };
+        let uses_lfs = || {
+            runtime::Runtime::new()
+                .unwrap()
+                .block_on(uses_lfs(Path::new("git"), repo.path()))
+                .unwrap()
+        };
+
+        git(&["init", "--quiet"]);
+        fs::create_dir(repo.path().join("assets")).unwrap();
+        fs::write(repo.path().join("assets/logo.png"), "").unwrap();
+        fs::write(repo.path().join("model.bin"), "").unwrap();
+        git(&["add", "."]);
+        assert!(!uses_lfs());
+
+        fs::write(
+            repo.path().join("assets/.gitattributes"),
+            "*.png filter=lfs diff=lfs merge=lfs -text\n",
+        )
+        .unwrap();
+        assert!(uses_lfs());
+
+        fs::remove_file(repo.path().join("assets/.gitattributes")).unwrap();
+        fs::write(repo.path().join(".git/info/attributes"), "*.bin filter=lfs\n").unwrap();
+        assert!(uses_lfs());
+    }
 
     #[test]
     fn fetches_when_on_a_different_branch() {
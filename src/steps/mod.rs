@@ -1,3 +1,5 @@
+#[cfg(target_os = "linux")]
+pub mod container_exec;
 pub mod containers;
 pub mod emacs;
 pub mod generic;
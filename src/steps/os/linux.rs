@@ -4,17 +4,17 @@ use std::path::{Path, PathBuf};
 use color_eyre::eyre::{Result, eyre};
 use ini::Ini;
 use rust_i18n::t;
-use tracing::{debug, warn};
+use tracing::{debug, error, warn};
 
 use crate::command::CommandExt;
-use crate::config::NixHandler;
+use crate::config::{ChrootMethod, NixHandler};
 use crate::error::{SkipStep, TopgradeError};
 use crate::execution_context::ExecutionContext;
 use crate::step::Step;
 use crate::steps::generic::IS_WSL;
 use crate::steps::os::archlinux;
 use crate::steps::unix::{NhSwitchArgs, can_nh_switch, nh_switch};
-use crate::sudo::SudoExecuteOpts;
+use crate::sudo::{Sudo, SudoExecuteOpts};
 use crate::terminal::{print_separator, print_warning, prompt_yesno};
 use crate::utils::{PathExt, require, require_flatpak, require_one, which};
 use crate::{HOME_DIR, output_changed_message};
@@ -53,7 +53,7 @@ pub enum Distribution {
 }
 
 impl Distribution {
-    fn parse_os_release(os_release: &Ini) -> Result<Self> {
+    pub fn parse_os_release(os_release: &Ini) -> Result<Self> {
         let section = os_release.general_section();
         let id = section.get("ID");
         let name = section.get("NAME");
@@ -115,6 +115,44 @@ impl Distribution {
         })
     }
 
+    /// Commands upgrading the packages of this distribution in a guest, such as a container or a chroot,
+    /// or `None` if it is not supported.
+    pub fn guest_upgrade_commands(self, yes: bool) -> Option<Vec<Vec<&'static str>>> {
+        use Distribution::*;
+
+        let with_yes = |command: &[&'static str], flag: &'static str| {
+            let mut command = command.to_vec();
+            if yes {
+                command.push(flag);
+            }
+            command
+        };
+
+        Some(match self {
+            Debian | KDENeon => vec![
+                vec!["apt-get", "update"],
+                with_yes(&["apt-get", "dist-upgrade"], "--yes"),
+            ],
+            CentOS | Fedora | Nobara | OpenMandriva => vec![with_yes(&["dnf", "upgrade"], "--assumeyes")],
+            Arch => vec![with_yes(&["pacman", "-Syu"], "--noconfirm")],
+            // apk does not ask for confirmation
+            Alpine | Chimera | Wolfi => vec![vec!["apk", "upgrade", "--update-cache"]],
+            OpenSuseTumbleweed | Suse => {
+                let zypper = if yes {
+                    vec!["zypper", "--non-interactive"]
+                } else {
+                    vec!["zypper"]
+                };
+                vec![
+                    [zypper.as_slice(), &["refresh"]].concat(),
+                    [zypper.as_slice(), &["dist-upgrade"]].concat(),
+                ]
+            }
+            Void => vec![with_yes(&["xbps-install", "-Su"], "--yes")],
+            _ => return None,
+        })
+    }
+
     fn match_fedora_variant(variant: &Option<&str>) -> Self {
         if let Some("Silverblue" | "Kinoite" | "Sericea" | "Onyx" | "IoT Edition" | "Sway Atomic" | "CoreOS") = variant
         {
@@ -174,6 +212,38 @@ impl Distribution {
         }
     }
 
+    /// The name of the distribution in `[distro.<id>]` configuration tables.
+    pub fn id(self) -> &'static str {
+        match self {
+            Distribution::Alpine => "alpine",
+            Distribution::AOSC => "aosc",
+            Distribution::Wolfi => "wolfi",
+            Distribution::Arch => "arch",
+            Distribution::Bedrock => "bedrock",
+            Distribution::CentOS => "centos",
+            Distribution::Chimera => "chimera",
+            Distribution::ClearLinux => "clear-linux-os",
+            Distribution::Fedora => "fedora",
+            Distribution::FedoraImmutable => "fedora-immutable",
+            Distribution::Debian => "debian",
+            Distribution::Gentoo => "gentoo",
+            Distribution::NILRT => "nilrt",
+            Distribution::OpenMandriva => "openmandriva",
+            Distribution::OpenSuseTumbleweed => "opensuse-tumbleweed",
+            Distribution::PCLinuxOS => "pclinuxos",
+            Distribution::Suse => "suse",
+            Distribution::SuseMicro => "opensuse-microos",
+            Distribution::Vanilla => "vanilla",
+            Distribution::Void => "void",
+            Distribution::Solus => "solus",
+            Distribution::Exherbo => "exherbo",
+            Distribution::NixOS => "nixos",
+            Distribution::KDELinux => "kde-linux",
+            Distribution::KDENeon => "neon",
+            Distribution::Nobara => "nobara",
+        }
+    }
+
     pub fn show_summary(self) {
         if let Distribution::Arch = self {
             archlinux::show_pacnew();
@@ -965,6 +1035,188 @@ pub fn run_protonup_update(ctx: &ExecutionContext) -> Result<()> {
     Ok(())
 }
 
+/// Split a directory `pattern` into the directory where its glob starts, and the depth of the
+/// directories it matches below it.
+fn split_chroot_pattern(pattern: &Path) -> (PathBuf, usize) {
+    let mut base = PathBuf::new();
+    let mut components = pattern.components();
+    for component in components.by_ref() {
+        if component.as_os_str().to_string_lossy().contains(['*', '?', '[']) {
+            return (base, components.count() + 1);
+        }
+        base.push(component);
+    }
+    (base, 0)
+}
+
+/// Root directories of the chroots to update, matching the `directories` patterns.
+///
+/// Directories such as `/var/lib/machines` are only readable by root, so they are listed with sudo.
+pub fn chroot_directories(ctx: &ExecutionContext) -> Result<Vec<PathBuf>> {
+    let patterns = ctx.config().chroot_directories();
+    if patterns.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sudo = ctx.require_sudo()?;
+
+    let mut directories = Vec::new();
+    for pattern in patterns {
+        let pattern = PathBuf::from(shellexpand::tilde(pattern).as_ref());
+        let (base, depth) = split_chroot_pattern(&pattern);
+        let depth = depth.to_string();
+
+        // `find` fails when the base directory does not exist, which only means nothing matches
+        let output = sudo
+            .execute(ctx, "find")?
+            .always()
+            .arg(&base)
+            .args(["-mindepth", &depth, "-maxdepth", &depth, "-type", "d", "-path"])
+            .arg(&pattern)
+            .output_checked_with_utf8(|_| Ok(()))?;
+        directories.extend(output.stdout.lines().map(PathBuf::from));
+    }
+
+    debug!("chroot directories: {directories:?}");
+    Ok(directories)
+}
+
+/// Filesystems mounted in a chroot for the duration of its update, unmounted when dropped.
+struct ChrootMounts<'a> {
+    ctx: &'a ExecutionContext<'a>,
+    sudo: &'a Sudo,
+    mounted: Vec<PathBuf>,
+}
+
+impl<'a> ChrootMounts<'a> {
+    /// Mount `/proc`, `/sys`, `/dev` and `/etc/resolv.conf` of the host in `directory`, which the
+    /// package managers need to run and to reach their repositories.
+    fn create(ctx: &'a ExecutionContext<'a>, sudo: &'a Sudo, directory: &Path) -> Result<Self> {
+        let mut mounts = ChrootMounts {
+            ctx,
+            sudo,
+            mounted: Vec::new(),
+        };
+
+        let proc = directory.join("proc");
+        sudo.execute(ctx, "mount")?
+            .args(["-t", "proc", "proc"])
+            .arg(&proc)
+            .status_checked()?;
+        mounts.mounted.push(proc);
+
+        for path in ["sys", "dev"] {
+            let target = directory.join(path);
+            sudo.execute(ctx, "mount")?
+                .arg("--rbind")
+                .arg(Path::new("/").join(path))
+                .arg(&target)
+                .status_checked()?;
+            mounts.mounted.push(target.clone());
+            sudo.execute(ctx, "mount")?
+                .arg("--make-rslave")
+                .arg(&target)
+                .status_checked()?;
+        }
+
+        // A link in the chroot would be resolved on the host, so only regular files are replaced
+        let resolv_conf = directory.join("etc/resolv.conf");
+        let is_file = sudo
+            .execute(ctx, "test")?
+            .always()
+            .arg("-f")
+            .arg(&resolv_conf)
+            .args(["-a", "!", "-L"])
+            .arg(&resolv_conf)
+            .output_checked_with(|_| Ok(()))
+            .is_ok_and(|output| output.status.success());
+        if is_file {
+            sudo.execute(ctx, "mount")?
+                .args(["--bind", "/etc/resolv.conf"])
+                .arg(&resolv_conf)
+                .status_checked()?;
+            mounts.mounted.push(resolv_conf);
+        }
+
+        Ok(mounts)
+    }
+}
+
+impl Drop for ChrootMounts<'_> {
+    fn drop(&mut self) {
+        for target in self.mounted.iter().rev() {
+            let unmounted = self
+                .sudo
+                .execute(self.ctx, "umount")
+                .and_then(|mut executor| executor.args(["--recursive", "--lazy"]).arg(target).status_checked());
+            if let Err(e) = unmounted {
+                error!("Failed to unmount {}: {e}", target.display());
+            }
+        }
+    }
+}
+
+/// Upgrade the packages in the chroot `directory`, with the package manager of the distribution
+/// detected from its `os-release`.
+pub fn run_chroot_update(ctx: &ExecutionContext, directory: &Path) -> Result<()> {
+    let sudo = ctx.require_sudo()?;
+
+    print_separator(format!("chroot ({})", directory.display()));
+
+    // `etc/os-release` is usually a link to `usr/lib/os-release`, which may be absolute
+    let os_release = ["usr/lib/os-release", "etc/os-release"]
+        .iter()
+        .find_map(|path| {
+            sudo.execute(ctx, "cat")
+                .and_then(|executor| executor.always().arg(directory.join(path)).output_checked_utf8())
+                .ok()
+        })
+        .ok_or_else(|| {
+            SkipStep(t!("No os-release file in {directory}", directory = directory.display()).to_string())
+        })?;
+    let distribution = Distribution::parse_os_release(&Ini::load_from_str(&os_release.stdout)?)?;
+    debug!("chroot {} runs {:?}", directory.display(), distribution);
+
+    let commands = distribution
+        .guest_upgrade_commands(ctx.config().yes(Step::Chroot))
+        .ok_or_else(|| {
+            SkipStep(
+                t!(
+                    "Unsupported distribution {distribution}",
+                    distribution = format!("{distribution:?}")
+                )
+                .to_string(),
+            )
+        })?;
+
+    let nspawn = match ctx.config().chroot_method() {
+        ChrootMethod::Auto => which("systemd-nspawn"),
+        ChrootMethod::Nspawn => Some(require("systemd-nspawn")?),
+        ChrootMethod::Chroot => None,
+    };
+    // `systemd-nspawn` sets up the filesystems of the directory itself
+    let _mounts = match &nspawn {
+        Some(_) => None,
+        None => Some(ChrootMounts::create(ctx, sudo, directory)?),
+    };
+    for command in commands {
+        let mut executor = match &nspawn {
+            Some(nspawn) => {
+                let mut executor = sudo.execute(ctx, nspawn)?;
+                executor.args(["--quiet", "--directory"]).arg(directory);
+                executor
+            }
+            None => {
+                let mut executor = sudo.execute(ctx, "chroot")?;
+                executor.arg(directory);
+                executor
+            }
+        };
+        executor.args(command).status_checked()?;
+    }
+
+    Ok(())
+}
+
 pub fn run_distrobox_update(ctx: &ExecutionContext) -> Result<()> {
     let distrobox = require("distrobox")?;
 
@@ -990,6 +1242,74 @@ pub fn run_distrobox_update(ctx: &ExecutionContext) -> Result<()> {
     .status_checked()
 }
 
+/// Steps disabled when running Topgrade in a box, which would update the host again: the boxes
+/// share the home directory, and so the configuration, of the host.
+const DISTROBOX_DISABLED_STEPS: [Step; 9] = [
+    Step::Chroot,
+    Step::ContainerExec,
+    Step::Containers,
+    Step::CustomCommands,
+    Step::Distrobox,
+    Step::GitRepos,
+    Step::Incus,
+    Step::Remotes,
+    Step::Toolbx,
+];
+
+/// Names of the boxes to run Topgrade in: the configured `containers`, or all of them.
+pub fn distrobox_boxes(ctx: &ExecutionContext) -> Result<Vec<String>> {
+    if let Some(containers) = ctx.config().distrobox_containers() {
+        return Ok(containers.clone());
+    }
+
+    let distrobox = require("distrobox")?;
+    let output = ctx
+        .execute(distrobox)
+        .always()
+        .args(["list", "--no-color"])
+        .arg_if(ctx.config().distrobox_root(), "--root")
+        .output_checked_utf8()?;
+
+    Ok(parse_distrobox_list(&output.stdout))
+}
+
+/// Parse the output of `distrobox list`, e.g. `ID | NAME | STATUS | IMAGE`, into the names of the boxes.
+fn parse_distrobox_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        // Skip the header
+        .skip(1)
+        .filter_map(|line| line.split('|').nth(1))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Run the host Topgrade in the box `name`.
+pub fn run_distrobox_topgrade(ctx: &ExecutionContext, name: &str) -> Result<()> {
+    let distrobox = require("distrobox")?;
+
+    print_separator(format!("Distrobox {name}"));
+    let topgrade_path = crate::steps::toolbx::host_topgrade_path()?;
+    let topgrade_prefix = format!("TOPGRADE_PREFIX=Distrobox {name}");
+
+    let mut command = ctx.execute(distrobox);
+    command
+        .arg("enter")
+        .arg_if(ctx.config().distrobox_root(), "--root")
+        .args(["-n", name, "--", "env", &topgrade_prefix, &topgrade_path])
+        .args(["--no-self-update", "--notify-end", "never", "--disable"])
+        .args(DISTROBOX_DISABLED_STEPS.iter().map(Step::cli_name));
+    if let Some(only) = ctx.config().distrobox_only() {
+        command.arg("--only").args(only.iter().map(Step::cli_name));
+    }
+    if ctx.config().yes(Step::Distrobox) {
+        command.arg("--yes");
+    }
+
+    command.status_checked()
+}
+
 pub fn run_dkp_pacman_update(ctx: &ExecutionContext) -> Result<()> {
     let dkp_pacman = require("dkp-pacman")?;
 
@@ -1198,6 +1518,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_split_chroot_pattern() {
+        assert_eq!(
+            split_chroot_pattern(Path::new("/var/lib/machines/*")),
+            (PathBuf::from("/var/lib/machines"), 1)
+        );
+        assert_eq!(
+            split_chroot_pattern(Path::new("/srv/chroot-*/rootfs")),
+            (PathBuf::from("/srv"), 2)
+        );
+        assert_eq!(
+            split_chroot_pattern(Path::new("/srv/chroots/bookworm")),
+            (PathBuf::from("/srv/chroots/bookworm"), 0)
+        );
+    }
+
+    #[test]
+    fn test_guest_upgrade_commands() {
+        assert_eq!(
+            Distribution::Debian.guest_upgrade_commands(true).unwrap(),
+            [vec!["apt-get", "update"], vec!["apt-get", "dist-upgrade", "--yes"]]
+        );
+        assert_eq!(
+            Distribution::Suse.guest_upgrade_commands(false).unwrap(),
+            [vec!["zypper", "refresh"], vec!["zypper", "dist-upgrade"]]
+        );
+        assert!(Distribution::NixOS.guest_upgrade_commands(true).is_none());
+    }
+
+    #[test]
+    fn test_parse_distrobox_list() {
+        let output = "ID           | NAME                 | STATUS             | IMAGE\n\
+                      d4e6c7a84f0a | arch                 | Up 2 hours         | quay.io/toolbx/arch-toolbox:latest\n\
+                      7b1c2e9f3d5a | ubuntu               | Exited (0) 3 days  | quay.io/toolbx/ubuntu-toolbox:24.04\n";
+        assert_eq!(parse_distrobox_list(output), ["arch", "ubuntu"]);
+    }
+
     #[test]
     fn test_wolfi() {
         test_template(include_str!("os_release/wolfi"), Distribution::Wolfi);
@@ -0,0 +1,82 @@
+use color_eyre::eyre::{OptionExt, Result};
+use rust_i18n::t;
+use tracing::{debug, error};
+
+use crate::command::CommandExt;
+use crate::error::SkipStep;
+use crate::execution_context::ExecutionContext;
+use crate::executor::Executor;
+use crate::step::Step;
+use crate::steps::remote::ssh::{PLATFORM_COMMAND, platform_matches};
+
+/// Where the host Topgrade is copied in the guests.
+const GUEST_TOPGRADE_PATH: &str = "/tmp/topgrade-host";
+
+/// A container or an instance sharing the kernel of the host, which can run the host Topgrade.
+pub trait Guest {
+    /// The name of the guest, prefixing the output of its Topgrade
+    fn name(&self) -> &str;
+
+    /// Copy the file `source` of the host to `target` in the guest.
+    fn copy(&self, ctx: &ExecutionContext, source: &str, target: &str) -> Result<()>;
+
+    /// An executor running a command, given as its arguments, in the guest with the `env`
+    /// variables, attached to the terminal when `interactive`.
+    fn exec(&self, ctx: &ExecutionContext, env: &[String], interactive: bool) -> Result<Executor>;
+}
+
+/// Copy the running Topgrade executable in `guest` and run it there with the `only` steps,
+/// removing it afterwards.
+///
+/// The guest is skipped when it cannot run the executable, e.g. a glibc build in an Alpine container.
+pub fn run_topgrade(ctx: &ExecutionContext, guest: &impl Guest, only: &[Step], yes: bool) -> Result<()> {
+    let platform = guest
+        .exec(ctx, &[], false)?
+        .always()
+        .args(["sh", "-c", PLATFORM_COMMAND])
+        .output_checked_utf8()?
+        .stdout;
+    let platform = platform.trim();
+    debug!("Platform of {}: {}", guest.name(), platform);
+    if !platform_matches(platform, env!("TARGET")) {
+        return Err(SkipStep(
+            t!(
+                "Cannot run Topgrade in {name}: {platform} does not match {target}",
+                name = guest.name(),
+                platform = platform,
+                target = env!("TARGET")
+            )
+            .to_string(),
+        )
+        .into());
+    }
+
+    let topgrade = std::env::current_exe()?;
+    let topgrade = topgrade.to_str().ok_or_eyre("Non-UTF-8 path")?;
+    guest.copy(ctx, topgrade, GUEST_TOPGRADE_PATH)?;
+
+    let mut command = guest.exec(ctx, &[format!("TOPGRADE_PREFIX={}", guest.name())], true)?;
+    command
+        .args([
+            GUEST_TOPGRADE_PATH,
+            "--no-self-update",
+            "--notify-end",
+            "never",
+            "--only",
+        ])
+        .args(only.iter().map(Step::cli_name));
+    if yes {
+        command.arg("--yes");
+    }
+    let result = command.status_checked();
+
+    // A failure to clean up must not hide the result of Topgrade
+    if let Err(e) = guest
+        .exec(ctx, &[], false)
+        .and_then(|mut rm| rm.args(["rm", "-f", GUEST_TOPGRADE_PATH]).status_checked())
+    {
+        error!("Failed to remove {GUEST_TOPGRADE_PATH} from {}: {e}", guest.name());
+    }
+
+    result
+}
@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+
+use color_eyre::eyre::Result;
+use ini::Ini;
+use rust_i18n::t;
+use tracing::debug;
+use wildmatch::WildMatch;
+
+use crate::command::CommandExt;
+use crate::config::IncusMode;
+use crate::error::SkipStep;
+use crate::execution_context::ExecutionContext;
+use crate::executor::Executor;
+use crate::step::Step;
+use crate::steps::os::linux::Distribution;
+use crate::steps::remote::guest::{self, Guest};
+use crate::terminal::print_separator;
+use crate::utils::require_one;
+
+/// How many seconds to wait for a started instance to accept commands.
+const START_TIMEOUT: u64 = 30;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Instance {
+    pub name: String,
+    running: bool,
+}
+
+/// The `incus` executable, or `lxc` from LXD, which has the same interface.
+fn incus() -> Result<PathBuf> {
+    require_one(["incus", "lxc"])
+}
+
+/// Instances to update, honoring the `instances` and `exclude` lists.
+pub fn instances(ctx: &ExecutionContext) -> Result<Vec<Instance>> {
+    let incus = incus()?;
+    let output = ctx
+        .execute(&incus)
+        .always()
+        .args(["list", "--format", "csv", "--columns", "ns"])
+        .output_checked_utf8()?;
+
+    let allowed: Option<Vec<_>> = ctx
+        .config()
+        .incus_instances()
+        .map(|patterns| patterns.iter().map(|pattern| WildMatch::new(pattern)).collect());
+    let excluded: Vec<_> = ctx
+        .config()
+        .incus_exclude()
+        .iter()
+        .map(|pattern| WildMatch::new(pattern))
+        .collect();
+
+    let instances = parse_instances(&output.stdout)
+        .into_iter()
+        .filter(|instance| {
+            allowed
+                .as_ref()
+                .is_none_or(|allowed| allowed.iter().any(|pattern| pattern.matches(&instance.name)))
+        })
+        .filter(|instance| !excluded.iter().any(|pattern| pattern.matches(&instance.name)))
+        .collect();
+    debug!("Incus instances: {:?}", instances);
+
+    Ok(instances)
+}
+
+/// Parse the output of `incus list --format csv --columns ns`, e.g. `build-1,RUNNING`.
+fn parse_instances(output: &str) -> Vec<Instance> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(','))
+        .map(|(name, state)| Instance {
+            name: name.to_string(),
+            running: state == "RUNNING",
+        })
+        .collect()
+}
+
+/// A stopped instance started for the update, stopped again when dropped.
+struct TemporaryStart<'a> {
+    incus: &'a Path,
+    instance: &'a Instance,
+    ctx: &'a ExecutionContext<'a>,
+}
+
+impl<'a> TemporaryStart<'a> {
+    fn create(incus: &'a Path, instance: &'a Instance, ctx: &'a ExecutionContext<'a>) -> Result<Self> {
+        ctx.execute(incus).args(["start", &instance.name]).status_checked()?;
+        let started = TemporaryStart { incus, instance, ctx };
+
+        // Virtual machines only accept commands once their agent is up
+        if !ctx.run_type().dry() {
+            for _ in 0..START_TIMEOUT {
+                let ready = ctx
+                    .execute(incus)
+                    .always()
+                    .args(["exec", &instance.name, "--", "true"])
+                    .output_checked_with(|_| Ok(()))
+                    .is_ok_and(|output| output.status.success());
+                if ready {
+                    break;
+                }
+                sleep(Duration::from_secs(1));
+            }
+        }
+
+        Ok(started)
+    }
+}
+
+impl Drop for TemporaryStart<'_> {
+    fn drop(&mut self) {
+        println!();
+        self.ctx
+            .execute(self.incus)
+            .args(["stop", &self.instance.name])
+            .status_checked()
+            .ok();
+    }
+}
+
+/// Update the instance, starting it first if it is stopped and `power_on` is enabled.
+pub fn update_instance(ctx: &ExecutionContext, instance: &Instance) -> Result<()> {
+    let incus = incus()?;
+
+    if !instance.running && !ctx.config().incus_power_on() {
+        return Err(SkipStep(t!("Skipping stopped instance {name}", name = instance.name).to_string()).into());
+    }
+
+    print_separator(format!("Incus ({})", instance.name));
+    let _start = if instance.running {
+        None
+    } else {
+        Some(TemporaryStart::create(&incus, instance, ctx)?)
+    };
+
+    match ctx.config().incus_mode() {
+        IncusMode::PackageManager => run_package_manager(ctx, &incus, &instance.name),
+        IncusMode::Topgrade => guest::run_topgrade(
+            ctx,
+            &RunningInstance {
+                incus: &incus,
+                name: &instance.name,
+            },
+            ctx.config().incus_only(),
+            ctx.config().yes(Step::Incus),
+        ),
+    }
+}
+
+/// Run the upgrade commands of the distribution of the instance, detected from its `os-release`.
+fn run_package_manager(ctx: &ExecutionContext, incus: &Path, name: &str) -> Result<()> {
+    let os_release = ctx
+        .execute(incus)
+        .always()
+        .args(["exec", name, "--", "cat", "/etc/os-release"])
+        .output_checked_utf8()?;
+    let distribution = Distribution::parse_os_release(&Ini::load_from_str(&os_release.stdout)?)?;
+    debug!("Incus instance {} runs {:?}", name, distribution);
+
+    let commands = distribution
+        .guest_upgrade_commands(ctx.config().yes(Step::Incus))
+        .ok_or_else(|| {
+            SkipStep(
+                t!(
+                    "Unsupported distribution {distribution}",
+                    distribution = format!("{distribution:?}")
+                )
+                .to_string(),
+            )
+        })?;
+    for command in commands {
+        ctx.execute(incus)
+            .args(["exec", name, "--"])
+            .args(command)
+            .status_checked()?;
+    }
+
+    Ok(())
+}
+
+/// An instance running Topgrade, with `incus exec`.
+struct RunningInstance<'a> {
+    incus: &'a Path,
+    name: &'a str,
+}
+
+impl Guest for RunningInstance<'_> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn copy(&self, ctx: &ExecutionContext, source: &str, target: &str) -> Result<()> {
+        ctx.execute(self.incus)
+            .args(["file", "push", source, &format!("{}{target}", self.name)])
+            .status_checked()
+    }
+
+    // `incus exec` attaches to the terminal by itself
+    fn exec(&self, ctx: &ExecutionContext, env: &[String], _interactive: bool) -> Result<Executor> {
+        let mut exec = ctx.execute(self.incus);
+        exec.args(["exec", self.name]);
+        for variable in env {
+            exec.args(["--env", variable]);
+        }
+        exec.arg("--");
+        Ok(exec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_instances() {
+        assert_eq!(
+            parse_instances("build-1,RUNNING\nbuild-2,STOPPED\n"),
+            [
+                Instance {
+                    name: "build-1".to_string(),
+                    running: true
+                },
+                Instance {
+                    name: "build-2".to_string(),
+                    running: false
+                }
+            ]
+        );
+    }
+}
@@ -1,2 +1,6 @@
-pub mod ssh;
-pub mod vagrant;
+#[cfg(target_os = "linux")]
+pub mod guest;
+#[cfg(target_os = "linux")]
+pub mod incus;
+pub mod ssh;
+pub mod vagrant;
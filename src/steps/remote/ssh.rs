@@ -1,52 +1,515 @@
-use color_eyre::eyre::Result;
+use std::borrow::Cow;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use clap::crate_version;
+use color_eyre::eyre::{Result, eyre};
+use etcetera::base_strategy::BaseStrategy;
 use rust_i18n::t;
+use semver::Version;
+use serde::Serialize;
+use tracing::debug;
 
+#[cfg(windows)]
+use crate::WINDOWS_DIRS;
+#[cfg(unix)]
+use crate::XDG_DIRS;
 use crate::{
-    command::CommandExt, error::SkipStep, execution_context::ExecutionContext, terminal::print_separator, utils,
+    command::CommandExt,
+    config::Remote,
+    error::SkipStep,
+    execution_context::ExecutionContext,
+    runner::Report,
+    terminal::{print_separator, print_warning},
+    utils,
 };
 
-fn prepare_async_ssh_command(args: &mut Vec<&str>) {
-    args.insert(0, "ssh");
-    args.push("--keep");
+/// Environment variable asking a remote Topgrade to print its report, see `print_report()`.
+pub const REPORT_ENV: &str = "TOPGRADE_REPORT";
+
+/// Prefix of the line carrying the JSON report of a remote Topgrade.
+const REPORT_MARKER: &str = "TOPGRADE_REPORT: ";
+
+/// Where `bootstrap()` installs Topgrade on remote hosts.
+const BOOTSTRAP_PATH: &str = "~/.local/bin/topgrade";
+
+/// The outcome of running Topgrade on a remote host in parallel mode.
+pub struct RemoteRun {
+    pub result: Result<()>,
+    /// The report of the remote Topgrade, if it printed one.
+    pub report: Option<Report<'static>>,
 }
 
-pub fn ssh_step(ctx: &ExecutionContext, hostname: &str) -> Result<()> {
-    let ssh = utils::require("ssh")?;
+/// Arguments of `ssh` running `command` in a login shell on `remote`, with the `env` variables set.
+fn ssh_arguments(remote: &Remote, env: &[String], command: &str) -> Vec<String> {
+    let mut args = vec![remote.destination.clone()];
+    args.extend(remote.ssh_arguments.iter().cloned());
+    if !env.is_empty() {
+        args.push("env".to_string());
+        args.extend(env.iter().cloned());
+    }
+    // `ssh` joins its arguments with spaces, quote the command so that it stays a single argument of `-c`
+    args.extend([
+        "$SHELL".to_string(),
+        "-lc".to_string(),
+        shell_words::quote(command).into_owned(),
+    ]);
+    args
+}
+
+/// Command running the Topgrade at `path` on `remote`, followed by `extra` arguments.
+fn topgrade_command(remote: &Remote, path: &str, extra: &[&str]) -> String {
+    let arguments = remote
+        .topgrade_arguments
+        .iter()
+        .map(String::as_str)
+        .chain(extra.iter().copied());
+    // `path` is left unquoted so that `~` gets expanded
+    [path.to_string(), shell_words::join(arguments)]
+        .join(" ")
+        .trim_end()
+        .to_string()
+}
+
+/// How to run Topgrade on a remote host, once it is ready.
+struct Launch {
+    /// Path to Topgrade on the host
+    topgrade: String,
+    /// Path to the configuration sent to the host for the run
+    config: Option<String>,
+}
 
-    let topgrade = ctx.config().remote_topgrade_path();
-    let mut args = vec!["-t", hostname];
+impl Launch {
+    /// Install Topgrade on `remote` and send it its configuration, as needed.
+    fn prepare(ctx: &ExecutionContext, ssh: &Path, remote: &Remote) -> Result<Self> {
+        let topgrade = if remote.bootstrap {
+            bootstrap(ctx, ssh, remote)?
+        } else {
+            remote.topgrade_path.clone()
+        };
 
-    if let Some(ssh_arguments) = ctx.config().ssh_arguments() {
-        args.extend(ssh_arguments.split_whitespace());
+        let config = match ctx.config().remote_config(remote)? {
+            Some(contents) => Some(upload_config(ctx, ssh, remote, &contents)?),
+            None => None,
+        };
+
+        Ok(Self { topgrade, config })
+    }
+
+    /// Command running Topgrade on `remote`, followed by `extra` arguments.
+    fn command(&self, remote: &Remote, extra: &[&str]) -> String {
+        let mut arguments = Vec::new();
+        if let Some(config) = &self.config {
+            arguments.extend(["--config", config.as_str()]);
+        }
+        arguments.extend(extra);
+        topgrade_command(remote, &self.topgrade, &arguments)
     }
 
-    let env = format!("TOPGRADE_PREFIX={hostname}");
-    args.extend(["env", &env, "$SHELL", "-lc", topgrade]);
+    /// Remove the configuration sent to `remote`, once the run is over.
+    fn clean_up(&self, ctx: &ExecutionContext, ssh: &Path, remote: &Remote) {
+        if let Some(config) = &self.config {
+            let result = ctx
+                .execute(ssh)
+                .args(ssh_arguments(
+                    remote,
+                    &[],
+                    &format!("rm -f {}", shell_words::quote(config)),
+                ))
+                .stdin(Stdio::null())
+                .status_checked();
+            if let Err(e) = result {
+                debug!("Failed to remove {config} on {}: {e:?}", remote.name);
+            }
+        }
+    }
+}
+
+/// Arguments of a local command opening an interactive `ssh` session running Topgrade in the background.
+fn async_ssh_command(remote: &Remote, env: &[String], launch: &Launch) -> Vec<String> {
+    let mut args = vec!["ssh".to_string(), "-t".to_string()];
+    args.extend(ssh_arguments(remote, env, &launch.command(remote, &["--keep"])));
+    args
+}
+
+pub fn ssh_step(ctx: &ExecutionContext, remote: &Remote) -> Result<()> {
+    let ssh = utils::require("ssh")?;
 
+    let launch = Launch::prepare(ctx, &ssh, remote)?;
+    let env = [format!("TOPGRADE_PREFIX={}", remote.name)];
+
+    // The configuration sent to the host is left behind when Topgrade runs in the background
     #[cfg(unix)]
     if ctx.config().run_in_tmux() && !ctx.run_type().dry() {
-        prepare_async_ssh_command(&mut args);
-        crate::tmux::run_command(ctx, hostname, &shell_words::join(args))?;
+        let args = async_ssh_command(remote, &env, &launch);
+        crate::tmux::run_command(ctx, &remote.name, &shell_words::join(args))?;
         return Err(SkipStep(String::from(t!("Remote Topgrade launched in Tmux"))).into());
     }
 
     if ctx.config().open_remotes_in_new_terminal() && !ctx.run_type().dry() && cfg!(windows) {
-        prepare_async_ssh_command(&mut args);
-        ctx.execute("wt").args(&args).spawn()?;
+        ctx.execute("wt")
+            .args(async_ssh_command(remote, &env, &launch))
+            .spawn()?;
         Err(SkipStep(String::from(t!("Remote Topgrade launched in an external terminal"))).into())
     } else {
-        let mut args = vec!["-t", hostname];
+        print_separator(format!("Remote ({})", remote.name));
+        println!("{}", t!("Connecting to {hostname}...", hostname = remote.destination));
+
+        let result = ctx
+            .execute(&ssh)
+            .arg("-t")
+            .args(ssh_arguments(remote, &env, &launch.command(remote, &[])))
+            .status_checked();
+        launch.clean_up(ctx, &ssh, remote);
+        result
+    }
+}
+
+/// Copy `contents` to a temporary file on `remote`, and return its path.
+fn upload_config(ctx: &ExecutionContext, ssh: &Path, remote: &Remote, contents: &str) -> Result<String> {
+    let upload = ssh_arguments(remote, &[], r#"sh -c 'f=$(mktemp) && cat > "$f" && echo "$f"'"#);
+    if ctx.run_type().dry() {
+        ctx.execute(ssh).args(upload).status_checked()?;
+        return Ok(String::from("/tmp/tmp.XXXXXXXXXX"));
+    }
+
+    let mut file = tempfile::tempfile()?;
+    file.write_all(contents.as_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let output = ctx.execute(ssh).args(upload).stdin(file).output_checked_utf8()?;
+    output
+        .stdout
+        .lines()
+        .last()
+        .map(|path| path.trim().to_string())
+        .filter(|path| !path.is_empty())
+        .ok_or_else(|| eyre!("Failed to create a temporary file on {}", remote.name))
+}
+
+/// Make sure `remote` has a Topgrade at least as recent as this one, copying this one to
+/// `BOOTSTRAP_PATH` if needed, and return the path of the Topgrade to run.
+fn bootstrap(ctx: &ExecutionContext, ssh: &Path, remote: &Remote) -> Result<String> {
+    let version = Version::parse(crate_version!())?;
+    for path in [remote.topgrade_path.as_str(), BOOTSTRAP_PATH] {
+        let remote_version = remote_version(ctx, ssh, remote, path);
+        debug!("Topgrade at {path} on {}: {remote_version:?}", remote.name);
+        if remote_version.is_some_and(|remote_version| remote_version >= version) {
+            return Ok(path.to_string());
+        }
+    }
+
+    let platform = ctx
+        .execute(ssh)
+        .always()
+        .args(ssh_arguments(remote, &[], PLATFORM_COMMAND))
+        .stdin(Stdio::null())
+        .output_checked_utf8()?
+        .stdout;
+    let platform = platform.lines().last().unwrap_or_default().trim();
+    if !platform_matches(platform, env!("TARGET")) {
+        print_warning(t!(
+            "Cannot install Topgrade on {hostname}: {platform} does not match {target}",
+            hostname = remote.name,
+            platform = platform,
+            target = env!("TARGET")
+        ));
+        return Ok(remote.topgrade_path.clone());
+    }
+
+    println!(
+        "{}",
+        t!(
+            "Installing Topgrade {version} on {hostname}",
+            version = version,
+            hostname = remote.name
+        )
+    );
+    let install = format!(
+        "mkdir -p ~/.local/bin && cat > {BOOTSTRAP_PATH}.tmp && chmod +x {BOOTSTRAP_PATH}.tmp && mv {BOOTSTRAP_PATH}.tmp {BOOTSTRAP_PATH}"
+    );
+    ctx.execute(ssh)
+        .args(ssh_arguments(remote, &[], &install))
+        .stdin(File::open(env::current_exe()?)?)
+        .status_checked()?;
+
+    Ok(BOOTSTRAP_PATH.to_string())
+}
+
+/// The version of the Topgrade at `path` on `remote`, if it can be run.
+fn remote_version(ctx: &ExecutionContext, ssh: &Path, remote: &Remote, path: &str) -> Option<Version> {
+    let output = ctx
+        .execute(ssh)
+        .always()
+        .args(ssh_arguments(remote, &[], &format!("{path} --version")))
+        .stdin(Stdio::null())
+        .output_checked_utf8()
+        .ok()?;
+
+    parse_version(&output.stdout)
+}
+
+/// Parse the output of `topgrade --version`, which may follow the messages of the login shell.
+fn parse_version(output: &str) -> Option<Version> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("topgrade "))
+        .and_then(|version| Version::parse(version.trim()).ok())
+}
+
+/// Prints the OS and architecture of a host, followed by the glibc version on glibc-based
+/// systems, e.g. `Linux x86_64 glibc 2.39`.
+pub const PLATFORM_COMMAND: &str = r#"echo "$(uname -sm) $(getconf GNU_LIBC_VERSION 2>/dev/null)""#;
+
+/// Whether a host whose `PLATFORM_COMMAND` output is `platform` can run binaries built for the
+/// `target` triple. Binaries linked with glibc do not run on musl-based systems like Alpine.
+pub fn platform_matches(platform: &str, target: &str) -> bool {
+    let mut fields = platform.split_whitespace();
+    let (Some(os), Some(arch)) = (fields.next(), fields.next()) else {
+        return false;
+    };
+    let glibc = fields.next() == Some("glibc");
+    let arch = match arch {
+        "amd64" => "x86_64",
+        "arm64" => "aarch64",
+        arch => arch,
+    };
+    let os = match os {
+        "Linux" => "linux",
+        "Darwin" => "darwin",
+        "FreeBSD" => "freebsd",
+        "NetBSD" => "netbsd",
+        "OpenBSD" => "openbsd",
+        _ => return false,
+    };
+
+    let needs_glibc = os == "linux" && target.rsplit('-').next().is_some_and(|env| env.starts_with("gnu"));
+    let mut parts = target.split('-');
+    parts.next() == Some(arch) && parts.any(|part| part == os) && (glibc || !needs_glibc)
+}
+
+/// Run Topgrade on `remotes`, at most `jobs` at a time, each of them logging to its own file.
+///
+/// The remote Topgrades run without a terminal, so they cannot prompt.
+pub fn ssh_parallel(ctx: &ExecutionContext, remotes: &[Remote], jobs: usize) -> Result<Vec<RemoteRun>> {
+    let ssh = utils::require("ssh")?;
+    let log_directory = log_directory();
+    if !ctx.run_type().dry() {
+        fs::create_dir_all(&log_directory)?;
+    }
+
+    print_separator(t!("Remotes"));
+
+    let next = AtomicUsize::new(0);
+    let mut runs: Vec<(usize, RemoteRun)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.clamp(1, remotes.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut runs = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(remote) = remotes.get(index) else {
+                            break;
+                        };
+                        runs.push((index, ssh_logged(ctx, &ssh, remote, &log_directory)));
+                    }
+                    runs
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("remote worker panicked"))
+            .collect()
+    });
 
-        if let Some(ssh_arguments) = ctx.config().ssh_arguments() {
-            args.extend(ssh_arguments.split_whitespace());
+    runs.sort_by_key(|(index, _)| *index);
+    Ok(runs.into_iter().map(|(_, run)| run).collect())
+}
+
+/// Run Topgrade on `remote` without a terminal, sending its output to a log file in `log_directory`.
+fn ssh_logged(ctx: &ExecutionContext, ssh: &Path, remote: &Remote, log_directory: &Path) -> RemoteRun {
+    let hostname = remote.name.as_str();
+    let launch = match Launch::prepare(ctx, ssh, remote) {
+        Ok(launch) => launch,
+        Err(e) => {
+            return RemoteRun {
+                result: Err(e),
+                report: None,
+            };
         }
+    };
+    let env = [format!("TOPGRADE_PREFIX={hostname}"), format!("{REPORT_ENV}=1")];
+    let args = ssh_arguments(remote, &env, &launch.command(remote, &[]));
+
+    if ctx.run_type().dry() {
+        return RemoteRun {
+            result: ctx.execute(ssh).args(&args).status_checked(),
+            report: None,
+        };
+    }
+
+    let log_path = log_directory.join(format!("{hostname}.log"));
+    println!(
+        "{}",
+        t!(
+            "Running Topgrade on {hostname}, logging to {path}",
+            hostname = hostname,
+            path = log_path.display()
+        )
+    );
+
+    let result = File::create(&log_path)
+        .and_then(|log| Ok((log.try_clone()?, log)))
+        .map_err(Into::into)
+        .and_then(|(stdout, stderr)| {
+            ctx.execute(ssh)
+                .args(&args)
+                .stdin(Stdio::null())
+                .stdout(stdout)
+                .stderr(stderr)
+                .status_checked()
+        });
 
-        let env = format!("TOPGRADE_PREFIX={hostname}");
-        args.extend(["env", &env, "$SHELL", "-lc", topgrade]);
+    launch.clean_up(ctx, ssh, remote);
+
+    match &result {
+        Ok(()) => println!("{}", t!("Topgrade finished on {hostname}", hostname = hostname)),
+        Err(_) => print_warning(t!(
+            "Topgrade failed on {hostname}, see {path}",
+            hostname = hostname,
+            path = log_path.display()
+        )),
+    }
+
+    let report = fs::read_to_string(&log_path).ok().and_then(|log| parse_report(&log));
+
+    RemoteRun { result, report }
+}
 
-        print_separator(format!("Remote ({hostname})"));
-        println!("{}", t!("Connecting to {hostname}...", hostname = hostname));
+/// Directory of the logs of the remote Topgrades run in parallel.
+fn log_directory() -> PathBuf {
+    #[cfg(unix)]
+    return XDG_DIRS.cache_dir().join("topgrade/remotes");
+
+    #[cfg(windows)]
+    return WINDOWS_DIRS.cache_dir().join("topgrade/remotes");
+}
+
+/// The report printed by `print_report()`.
+#[derive(Serialize)]
+struct MachineReport<'a, 'b> {
+    steps: &'a Report<'b>,
+    /// What some steps updated, by step key, e.g. the states of the Git repositories
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    details: &'a [(Cow<'b, str>, serde_json::Value)],
+}
+
+/// Print the `report` of this run and the `details` of its steps on a single line, for the
+/// Topgrade running this one remotely, or any other program.
+pub fn print_report(report: &Report, details: &[(Cow<str>, serde_json::Value)]) {
+    if let Ok(json) = serde_json::to_string(&MachineReport { steps: report, details }) {
+        println!("{REPORT_MARKER}{json}");
+    }
+}
+
+/// Find the steps of the report printed by `print_report()` in the output of a remote Topgrade.
+fn parse_report(output: &str) -> Option<Report<'static>> {
+    let json = output
+        .lines()
+        .rev()
+        .find_map(|line| line.trim_end().strip_prefix(REPORT_MARKER))?;
+    let mut report: serde_json::Value = serde_json::from_str(json).ok()?;
+    serde_json::from_value(report["steps"].take()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::StepResult;
+
+    #[test]
+    fn test_parse_report() {
+        let report: Report = vec![
+            ("System update".into(), StepResult::Success),
+            ("Cargo".into(), StepResult::Failure),
+            ("Flatpak".into(), StepResult::Skipped("not installed".to_string())),
+        ];
+        let details = [(
+            "Git Repositories".into(),
+            serde_json::json!([{"repo": "/src/topgrade"}]),
+        )];
+        let json = serde_json::to_string(&MachineReport {
+            steps: &report,
+            details: &details,
+        })
+        .unwrap();
+        assert!(json.contains(r#""details":[["Git Repositories",[{"repo":"/src/topgrade"}]]]"#));
+        let output = format!("── Summary ──\nSystem update: OK\n{REPORT_MARKER}{json}\r\n");
+
+        let parsed = parse_report(&output).unwrap();
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[1].0, "Cargo");
+        assert!(parsed[1].1.failed());
+        assert!(matches!(&parsed[2].1, StepResult::Skipped(reason) if reason == "not installed"));
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("Welcome to pi!\ntopgrade 17.9.0\n"),
+            Some(Version::new(17, 9, 0))
+        );
+        assert_eq!(parse_version("bash: topgrade: command not found\n"), None);
+    }
+
+    #[test]
+    fn test_platform_matches() {
+        assert!(platform_matches("Linux x86_64 glibc 2.39", "x86_64-unknown-linux-gnu"));
+        assert!(platform_matches(
+            "Linux aarch64 glibc 2.36",
+            "aarch64-unknown-linux-musl"
+        ));
+        assert!(platform_matches("Linux aarch64", "aarch64-unknown-linux-musl"));
+        assert!(!platform_matches("Linux x86_64", "x86_64-unknown-linux-gnu"));
+        assert!(platform_matches("Darwin arm64", "aarch64-apple-darwin"));
+        assert!(platform_matches("FreeBSD amd64", "x86_64-unknown-freebsd"));
+        assert!(!platform_matches("Linux aarch64", "x86_64-unknown-linux-gnu"));
+        assert!(!platform_matches("Darwin x86_64", "x86_64-unknown-linux-gnu"));
+        assert!(!platform_matches("", "x86_64-unknown-linux-gnu"));
+    }
+
+    #[test]
+    fn test_topgrade_command() {
+        let remote = Remote {
+            name: "pi".to_string(),
+            destination: "pi".to_string(),
+            ssh_arguments: Vec::new(),
+            topgrade_path: "topgrade".to_string(),
+            topgrade_arguments: vec!["--only".to_string(), "system".to_string()],
+            bootstrap: false,
+            config: None,
+        };
+
+        assert_eq!(
+            topgrade_command(&remote, "~/.local/bin/topgrade", &["--keep"]),
+            "~/.local/bin/topgrade --only system --keep"
+        );
+        assert_eq!(
+            ssh_arguments(&remote, &[], &topgrade_command(&remote, "topgrade", &[])),
+            ["pi", "$SHELL", "-lc", "'topgrade --only system'"]
+        );
+    }
 
-        ctx.execute(ssh).args(&args).status_checked()
+    #[test]
+    fn test_parse_report_missing() {
+        assert!(parse_report("System update: OK\n").is_none());
+        assert!(parse_report(&format!("{REPORT_MARKER}not json")).is_none());
     }
 }
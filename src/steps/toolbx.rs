@@ -7,6 +7,7 @@ use crate::{execution_context::ExecutionContext, utils::require};
 use std::path::Path;
 use std::path::PathBuf;
 use tracing::debug;
+use wildmatch::WildMatch;
 
 fn list_toolboxes(ctx: &ExecutionContext, toolbx: &Path) -> Result<Vec<String>> {
     let output = ctx
@@ -30,40 +31,86 @@ fn list_toolboxes(ctx: &ExecutionContext, toolbx: &Path) -> Result<Vec<String>>
     Ok(proc)
 }
 
-pub fn run_toolbx(ctx: &ExecutionContext) -> Result<()> {
+/// Path of the running Topgrade executable in the containers mounting the host at `/run/host`,
+/// such as toolboxes and distroboxes.
+pub fn host_topgrade_path() -> Result<String> {
+    let mut topgrade_path = PathBuf::from("/run/host");
+    // Skip 1 to eliminate the path root, otherwise push overwrites the path
+    topgrade_path.push(std::env::current_exe()?.components().skip(1).collect::<PathBuf>());
+    Ok(topgrade_path.to_str().ok_or_eyre("Non-UTF-8 path")?.to_string())
+}
+
+/// Toolboxes to update: all of them, except the excluded ones.
+pub fn toolboxes(ctx: &ExecutionContext) -> Result<Vec<String>> {
     let toolbx = require("toolbox")?;
 
-    print_separator("Toolbx");
-    let toolboxes = list_toolboxes(ctx, &toolbx)?;
+    let exclude: Vec<_> = ctx
+        .config()
+        .toolbx_exclude()
+        .iter()
+        .map(|p| WildMatch::new(p))
+        .collect();
+    let toolboxes = list_toolboxes(ctx, &toolbx)?
+        .into_iter()
+        .filter(|tb| !exclude.iter().any(|pattern| pattern.matches(tb)))
+        .collect();
     debug!("Toolboxes to inspect: {:?}", toolboxes);
 
-    let mut topgrade_path = PathBuf::from("/run/host");
-    // Path of the running Topgrade executable
-    // Skip 1 to eliminate the path root, otherwise push overwrites the path
-    topgrade_path.push(std::env::current_exe()?.components().skip(1).collect::<PathBuf>());
-    let topgrade_path = topgrade_path.to_str().ok_or_eyre("Non-UTF-8 path")?;
+    Ok(toolboxes)
+}
 
-    for tb in toolboxes.iter() {
-        let topgrade_prefix = format!("TOPGRADE_PREFIX='Toolbx {tb}'");
-        let mut args = vec![
+/// Whether the toolbox `tb` has its own system-wide Topgrade configuration.
+fn has_own_config(ctx: &ExecutionContext, toolbx: &Path, tb: &str) -> bool {
+    ctx.execute(toolbx)
+        .always()
+        .args([
+            "run",
+            "-c",
+            tb,
+            "test",
+            "-e",
+            "/etc/topgrade/topgrade.toml",
+            "-o",
+            "-d",
+            "/etc/topgrade.d",
+        ])
+        .output_checked_with(|_| Ok(()))
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Run the host Topgrade in the toolbox `tb`.
+pub fn run_toolbx(ctx: &ExecutionContext, tb: &str) -> Result<()> {
+    let toolbx = require("toolbox")?;
+
+    print_separator(format!("Toolbx {tb}"));
+    let toolbox = ctx.config().toolbox(tb)?;
+    let topgrade_path = host_topgrade_path()?;
+
+    let mut command = ctx.execute(&toolbx);
+    command
+        .args([
             "run",
             "-c",
             tb,
             "env",
-            &topgrade_prefix,
-            topgrade_path,
-            "--only",
-            "system",
-            "--no-self-update",
-            "--notify-end",
-            "never",
-        ];
-        if ctx.config().yes(Step::Toolbx) {
-            args.push("--yes");
+            &format!("TOPGRADE_PREFIX=Toolbx {tb}"),
+            &topgrade_path,
+        ])
+        .args(["--no-self-update", "--notify-end", "never"]);
+    match toolbox.only {
+        Some(only) => {
+            command.arg("--only").args(only.iter().map(Step::cli_name));
         }
-
-        ctx.execute(&toolbx).args(&args).status_checked()?;
+        // Let the configuration of the toolbox choose the steps
+        None if has_own_config(ctx, &toolbx, tb) => (),
+        None => {
+            command.args(["--only", "system"]);
+        }
+    }
+    command.args(&toolbox.arguments);
+    if ctx.config().yes(Step::Toolbx) {
+        command.arg("--yes");
     }
 
-    Ok(())
+    command.status_checked()
 }
@@ -11,7 +11,8 @@ use color_eyre::eyre::Result;
 use color_eyre::eyre::eyre;
 use itertools::Itertools;
 use rust_i18n::t;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use strum::Display;
 use thiserror::Error;
 #[cfg(windows)]
@@ -558,7 +559,7 @@ impl Sudo {
 // We always define both though, so that we don't have to put
 // #[cfg(...)] everywhere.
 
-#[derive(Clone, Copy, Debug, Display, Deserialize)]
+#[derive(Clone, Copy, Debug, Display, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "lowercase")]
 pub enum SudoKind {
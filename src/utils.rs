@@ -339,6 +339,7 @@ pub fn is_elevated() -> bool {
 
 pub mod merge_strategies {
     use indexmap::IndexMap;
+    use merge2::Merge;
     use std::hash::Hash;
     use std::mem;
 
@@ -374,6 +375,27 @@ pub mod merge_strategies {
             mem::swap(left, right);
         }
     }
+
+    /// Like `indexmap_merge_opt`, but values present in both maps are merged instead of replaced
+    pub fn indexmap_recursive_merge_opt<T: Hash + Eq, U: Merge>(
+        left: &mut Option<IndexMap<T, U>>,
+        right: &mut Option<IndexMap<T, U>>,
+    ) {
+        if let Some(left_inner) = left {
+            if let Some(right_inner) = right {
+                for (key, mut value) in mem::take(right_inner) {
+                    match left_inner.get_mut(&key) {
+                        Some(left_value) => left_value.merge(&mut value),
+                        None => {
+                            left_inner.insert(key, value);
+                        }
+                    }
+                }
+            }
+        } else {
+            mem::swap(left, right);
+        }
+    }
 }
 
 /// Return `Err(SkipStep)` if `python` is a Python 2 or shim.